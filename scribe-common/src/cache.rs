@@ -0,0 +1,216 @@
+//! Caches for the results of expensive operations, persisted between builds.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::{debug, warn};
+
+/// Cache for results keyed by a hash of their inputs, see [`cache_key`].
+///
+/// The cache can be cloned to share it between documents and persisted to
+/// disk between builds.
+#[derive(Debug)]
+pub struct Cache<V> {
+    inner: Arc<Mutex<CacheInner<V>>>,
+}
+
+#[derive(Debug)]
+struct CacheInner<V> {
+    entries: HashMap<String, V>,
+    used: HashSet<String>,
+}
+
+/// On-disk representation of [`Cache`].
+#[derive(Deserialize, Serialize)]
+struct CacheFile<V> {
+    version: String,
+    entries: HashMap<String, V>,
+}
+
+impl<V> Cache<V>
+where
+    V: Clone + Serialize + DeserializeOwned,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a cache from disk.
+    ///
+    /// Returns an empty cache if the file does not exist, was written by a
+    /// different version or is corrupt.
+    pub fn load(path: &Path) -> Result<Self, CacheError> {
+        if !path.exists() {
+            return Ok(Self::new());
+        }
+
+        let content = std::fs::read(path)?;
+
+        let file: CacheFile<V> = match serde_json::from_slice(&content) {
+            Ok(file) => file,
+            Err(error) => {
+                warn!("discarding invalid cache {}: {}", path.display(), error);
+                return Ok(Self::new());
+            }
+        };
+
+        if file.version != env!("CARGO_PKG_VERSION") {
+            debug!(
+                "discarding cache {} from version {}",
+                path.display(),
+                file.version
+            );
+            return Ok(Self::new());
+        }
+
+        let inner = CacheInner {
+            entries: file.entries,
+            used: HashSet::new(),
+        };
+
+        Ok(Self {
+            inner: Arc::new(Mutex::new(inner)),
+        })
+    }
+
+    /// Write the cache to disk.
+    ///
+    /// Only the entries that have been used since the cache was loaded are
+    /// written, so that results for removed inputs do not pile up.
+    pub fn save(&self, path: &Path) -> Result<(), CacheError> {
+        let file = {
+            let inner = self.inner.lock().unwrap();
+
+            let entries = inner
+                .entries
+                .iter()
+                .filter(|(key, _)| inner.used.contains(*key))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+
+            CacheFile {
+                version: env!("CARGO_PKG_VERSION").into(),
+                entries,
+            }
+        };
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(path, serde_json::to_vec(&file)?)?;
+        Ok(())
+    }
+
+    /// The cached result for `key`, marking it as used.
+    pub fn get(&self, key: &str) -> Option<V> {
+        let mut inner = self.inner.lock().unwrap();
+        let value = inner.entries.get(key).cloned()?;
+        inner.used.insert(key.to_string());
+        Some(value)
+    }
+
+    pub fn insert(&self, key: String, value: V) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.used.insert(key.clone());
+        inner.entries.insert(key, value);
+    }
+}
+
+impl<V> Clone for Cache<V> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<V> Default for Cache<V> {
+    fn default() -> Self {
+        let inner = CacheInner {
+            entries: HashMap::new(),
+            used: HashSet::new(),
+        };
+
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+}
+
+/// The key for a result computed from `inputs`.
+///
+/// The key is the SHA-256 of the inputs serialized to JSON, so that it is
+/// stable across builds and versions of Rust.
+pub fn cache_key(inputs: impl Serialize) -> String {
+    // Serializing plain data like strings, maps and tuples cannot fail.
+    let json = serde_json::to_vec(&inputs).expect("cache inputs must serialize");
+
+    Sha256::digest(json)
+        .iter()
+        .fold(String::with_capacity(64), |mut key, byte| {
+            let _ = write!(key, "{:02x}", byte);
+            key
+        })
+}
+
+/// Error produced while loading or saving a [`Cache`].
+#[derive(Debug, Error)]
+pub enum CacheError {
+    /// Error while reading or writing the cache file.
+    #[error("failed to access cache: {0}")]
+    Io(#[from] std::io::Error),
+    /// Error while (de)serializing the cache file.
+    #[error("invalid cache: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_key_is_stable() {
+        // SHA-256 of `["a",1]`.
+        assert_eq!(
+            cache_key(("a", 1)),
+            "135f17a475a61afdeeaf3759ad2e45ad1c7abb192395abe47e41fc8e395dc1a9"
+        );
+        assert_ne!(cache_key(("a", 1)), cache_key(("a", 2)));
+    }
+
+    #[test]
+    fn save_keeps_used_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+
+        let cache = Cache::new();
+        cache.insert("a".into(), 1);
+        cache.insert("b".into(), 2);
+        cache.save(&path).unwrap();
+
+        let cache = Cache::<i32>::load(&path).unwrap();
+        assert_eq!(cache.get("a"), Some(1));
+        cache.save(&path).unwrap();
+
+        let cache = Cache::<i32>::load(&path).unwrap();
+        assert_eq!(cache.get("a"), Some(1));
+        assert_eq!(cache.get("b"), None);
+    }
+
+    #[test]
+    fn load_discards_corrupt_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        std::fs::write(&path, "{\"version\":").unwrap();
+
+        let cache = Cache::<i32>::load(&path).unwrap();
+        assert_eq!(cache.get("a"), None);
+    }
+}
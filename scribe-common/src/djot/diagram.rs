@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::time::Duration;
+
+use jotdown::{Attributes, Container, Event};
+use thiserror::Error;
+use tracing::{debug, trace};
+
+use super::error::Located;
+use super::html::attributes_html;
+use crate::cache::{Cache, cache_key};
+use crate::tools::ToolError;
+use crate::tools::diagram::DiagramTool;
+use crate::tools::svg::{SharedSvgProcessor, SvgProcessor};
+
+/// Render code blocks with diagrams to inline SVG using external tools.
+///
+/// A code block is rendered by the tool for its language, e.g. the `dot`
+/// command for code blocks in `dot`. The SVG is wrapped in a `div` with the
+/// `diagram` class and the attributes of the code block. Like math rendered by
+/// LaTeX, its ids are namespaced and black follows the text color.
+#[derive(Debug)]
+pub struct DiagramBlocks<'a, I> {
+    inner: I,
+    tools: HashMap<String, DiagramTool>,
+    timeout: Duration,
+    cache: Option<Cache<String>>,
+    svg: SharedSvgProcessor,
+    buffer: Vec<Event<'a>>,
+    /// The range of the element that the buffered events replace.
+    range: Range<usize>,
+}
+
+impl<'a, I> DiagramBlocks<'a, I> {
+    /// Create a filter that renders code blocks with the tools for their
+    /// languages.
+    pub fn new(inner: I, tools: HashMap<String, DiagramTool>) -> Self {
+        Self {
+            inner,
+            tools,
+            timeout: DEFAULT_TIMEOUT,
+            cache: None,
+            svg: SharedSvgProcessor::new(SvgProcessor::new("diagram")),
+            buffer: Vec::with_capacity(2),
+            range: 0..0,
+        }
+    }
+
+    /// Kill tools that do not finish within `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Reuse renderings from the given cache.
+    pub fn with_cache(mut self, cache: Cache<String>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Process the SVGs with a processor shared by the page.
+    pub fn with_svg_processor(mut self, svg: SharedSvgProcessor) -> Self {
+        self.svg = svg;
+        self
+    }
+
+    fn render(&self, tool: &DiagramTool, source: &str) -> Result<String, DiagramError> {
+        let key = cache_key((tool, source));
+
+        if let Some(svg) = self.cache.as_ref().and_then(|cache| cache.get(&key)) {
+            trace!("using cached diagram");
+            return Ok(svg);
+        }
+
+        debug!("rendering diagram with `{}`", tool.command.join(" "));
+        let svg = tool.to_svg_blocking(source, self.timeout)?;
+
+        if let Some(cache) = &self.cache {
+            cache.insert(key, svg.clone());
+        }
+
+        Ok(svg)
+    }
+}
+
+/// The default timeout for tools.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl<'a, I> Iterator for DiagramBlocks<'a, I>
+where
+    I: Iterator<Item = (Event<'a>, Range<usize>)>,
+{
+    type Item = Result<(Event<'a>, Range<usize>), Located<DiagramError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.buffer.pop() {
+            return Some(Ok((event, self.range.clone())));
+        }
+
+        let (tool, attributes) = match self.inner.next()? {
+            (Event::Start(Container::CodeBlock { language }, attributes), range)
+                if self.tools.contains_key(language) =>
+            {
+                self.range = range;
+                (self.tools[language].clone(), attributes)
+            }
+            event => return Some(Ok(event)),
+        };
+
+        let mut source = String::new();
+
+        for (event, range) in self.inner.by_ref() {
+            self.range.end = range.end;
+
+            match event {
+                Event::End(_) => break,
+                Event::Str(str) => source.push_str(&str),
+                _ => {
+                    let range = self.range.clone();
+                    return Some(Err(Located::new(DiagramError::Unexpected, range)));
+                }
+            }
+        }
+
+        let svg = match self.render(&tool, &source) {
+            Ok(svg) => self.svg.process(&svg),
+            Err(err) => return Some(Err(Located::new(err, self.range.clone()))),
+        };
+
+        let html = format!(
+            "<div{}>\n{}\n</div>",
+            attributes_html(&["diagram"], &attributes),
+            svg
+        );
+
+        self.buffer.extend([
+            Event::End(Container::RawBlock { format: "html" }),
+            Event::Str(html.into()),
+        ]);
+
+        Some(Ok((
+            Event::Start(Container::RawBlock { format: "html" }, Attributes::new()),
+            self.range.clone(),
+        )))
+    }
+}
+
+/// Error produced by [`DiagramBlocks`].
+#[derive(Debug, Error)]
+pub enum DiagramError {
+    /// Error while running the tool.
+    #[error("failed to render diagram: {0}")]
+    Tool(#[from] ToolError),
+    /// Unexpected [`Event`] in code block.
+    #[error("unexpected event in diagram")]
+    Unexpected,
+}
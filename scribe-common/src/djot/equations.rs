@@ -0,0 +1,436 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use jotdown::{
+    AttributeKind, AttributeValue, Attributes, Container, Event, LinkType, SpanLinkType,
+};
+use thiserror::Error;
+use tracing::trace;
+
+use super::error::Located;
+
+/// Number labelled display math and resolve references to it.
+///
+/// Display math is labelled either with an id attribute, as in
+/// `` $$`e^{i\pi} = -1`{#eq:euler} ``, or with `\label{euler}` in the math,
+/// which is equivalent to the id `eq:euler`. Labelled equations are numbered
+/// in document order, tagged with their number and get the id and the
+/// `equation` class, so that they can be linked to. Labelled equations that
+/// are already tagged with `\tag{...}` keep their tag and are not numbered,
+/// and labels must be unique within the document.
+///
+/// References are resolved to the equation number in parentheses:
+///
+/// - Links without text to the id of an equation, e.g. `[](#eq:euler)`.
+/// - `\eqref{euler}` in math. When it is all there is in an inline formula, it
+///   is replaced by a link. Within larger formulas it becomes the number in
+///   parentheses without a link, as KaTeX only renders links with its `trust`
+///   option. `\ref{euler}` resolves to the number alone.
+///
+/// The whole document is buffered, so that references can precede equations.
+#[derive(Debug, Clone)]
+pub struct NumberEquations<'a> {
+    events: std::iter::Peekable<std::vec::IntoIter<(Event<'a>, Range<usize>)>>,
+    /// The tags of the equations by their ids, usually their numbers.
+    tags: HashMap<String, String>,
+    /// The ids of the equations emitted so far.
+    seen: HashSet<String>,
+    counter: usize,
+    buffer: Vec<Event<'a>>,
+    /// The range of the element that the buffered events replace.
+    range: Range<usize>,
+}
+
+impl<'a> NumberEquations<'a> {
+    pub fn new(inner: impl IntoIterator<Item = (Event<'a>, Range<usize>)>) -> Self {
+        let events: Vec<_> = inner.into_iter().collect();
+        let mut tags = HashMap::new();
+        let mut counter = 0;
+        let mut events_iter = events.iter();
+
+        while let Some((event, _)) = events_iter.next() {
+            let Event::Start(Container::Math { display: true }, attributes) = event else {
+                continue;
+            };
+
+            let (math, _) = collect_math(&mut events_iter.by_ref().cloned());
+
+            let Some(id) = equation_id(attributes, &math) else {
+                continue;
+            };
+
+            // Duplicates are reported when they are emitted.
+            if tags.contains_key(&id) {
+                continue;
+            }
+
+            let tag = match existing_tag(&math) {
+                Some(tag) => tag.to_string(),
+                None => {
+                    counter += 1;
+                    counter.to_string()
+                }
+            };
+
+            trace!("equation `{}` has tag {}", id, tag);
+            tags.insert(id, tag);
+        }
+
+        Self {
+            events: events.into_iter().peekable(),
+            tags,
+            seen: HashSet::new(),
+            counter: 0,
+            buffer: Vec::with_capacity(4),
+            range: 0..0,
+        }
+    }
+
+    /// The tag of the equation referred to by `label`.
+    fn lookup(&self, label: &str) -> Result<(String, &str), EquationError> {
+        [format!("eq:{}", label), label.to_string()]
+            .into_iter()
+            .find_map(|id| {
+                let tag = self.tags.get(&id)?;
+                Some((id, tag.as_str()))
+            })
+            .ok_or_else(|| EquationError::UnknownLabel(label.to_string()))
+    }
+
+    /// Replace `\eqref` and `\ref` in math with the equation numbers.
+    fn resolve_math_refs(&self, math: &str) -> Result<String, EquationError> {
+        let mut output = String::with_capacity(math.len());
+        let mut rest = math;
+
+        while let Some((start, command)) = find_ref(rest) {
+            output.push_str(&rest[..start]);
+            rest = &rest[start + command.len()..];
+
+            let Some((label, after)) = rest.split_once('}') else {
+                return Err(EquationError::Malformed(math.to_string()));
+            };
+
+            let (_, tag) = self.lookup(label.trim())?;
+
+            match command {
+                "\\eqref{" => output.push_str(&format!("\\text{{({})}}", tag)),
+                _ => output.push_str(&format!("\\text{{{}}}", tag)),
+            }
+
+            rest = after;
+        }
+
+        output.push_str(rest);
+        Ok(output)
+    }
+
+    fn display_math(
+        &mut self,
+        mut attributes: Attributes<'a>,
+        math: String,
+    ) -> Result<Event<'a>, EquationError> {
+        let Some(id) = equation_id(&attributes, &math) else {
+            let math = self.resolve_math_refs(&math)?;
+            return Ok(self.emit_math(true, attributes, math));
+        };
+
+        if !self.seen.insert(id.clone()) {
+            return Err(EquationError::DuplicateLabel(id));
+        }
+
+        let math = strip_label(&math);
+        let math = self.resolve_math_refs(&math)?;
+
+        // KaTeX fails on equations with more than one tag.
+        let math = match existing_tag(&math) {
+            Some(_) => math,
+            None => {
+                self.counter += 1;
+                format!("{}\\tag{{{}}}", math, self.counter)
+            }
+        };
+
+        if !attributes.contains_key("id") {
+            attributes.push((AttributeKind::Id, AttributeValue::from(id)));
+        }
+
+        attributes.push((AttributeKind::Class, AttributeValue::from("equation")));
+        Ok(self.emit_math(true, attributes, math))
+    }
+
+    fn inline_math(
+        &mut self,
+        attributes: Attributes<'a>,
+        math: String,
+    ) -> Result<Event<'a>, EquationError> {
+        let label = math
+            .trim()
+            .strip_prefix("\\eqref{")
+            .and_then(|rest| rest.strip_suffix('}'))
+            .filter(|label| !label.contains(['{', '}']));
+
+        let Some(label) = label else {
+            let math = self.resolve_math_refs(&math)?;
+            return Ok(self.emit_math(false, attributes, math));
+        };
+
+        let (id, tag) = self.lookup(label.trim())?;
+        let text = format!("({})", tag);
+        let link = Container::Link(
+            format!("#{}", id).into(),
+            LinkType::Span(SpanLinkType::Inline),
+        );
+
+        self.buffer
+            .extend([Event::End(link.clone()), Event::Str(text.into())]);
+
+        Ok(Event::Start(link, attributes))
+    }
+
+    fn link(
+        &mut self,
+        destination: &str,
+        container: Container<'a>,
+        attributes: Attributes<'a>,
+    ) -> Result<Event<'a>, EquationError> {
+        let empty = match self.events.peek() {
+            Some((Event::End(Container::Link(..)), range)) => {
+                self.range.end = range.end;
+                true
+            }
+            _ => false,
+        };
+        let id = destination.strip_prefix('#');
+
+        let tag = match id {
+            Some(id) if empty => self.tags.get(id),
+            _ => None,
+        };
+
+        match (tag, id) {
+            (Some(tag), _) => {
+                self.buffer.push(Event::Str(format!("({})", tag).into()));
+            }
+            (None, Some(id)) if empty && id.starts_with("eq:") => {
+                self.events.next();
+                return Err(EquationError::UnknownLabel(id.to_string()));
+            }
+            _ => {}
+        }
+
+        Ok(Event::Start(container, attributes))
+    }
+
+    /// Queue the events of a math element and return its start event.
+    fn emit_math(&mut self, display: bool, attributes: Attributes<'a>, math: String) -> Event<'a> {
+        self.buffer.extend([
+            Event::End(Container::Math { display }),
+            Event::Str(math.into()),
+        ]);
+
+        Event::Start(Container::Math { display }, attributes)
+    }
+}
+
+impl<'a> Iterator for NumberEquations<'a> {
+    type Item = Result<(Event<'a>, Range<usize>), Located<EquationError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.buffer.pop() {
+            return Some(Ok((event, self.range.clone())));
+        }
+
+        let (event, range) = self.events.next()?;
+        self.range = range;
+
+        let result = match event {
+            Event::Start(Container::Math { display }, attributes) => {
+                let (math, end) = collect_math(&mut self.events);
+                self.range.end = self.range.end.max(end);
+
+                match display {
+                    true => self.display_math(attributes, math),
+                    false => self.inline_math(attributes, math),
+                }
+            }
+            Event::Start(Container::Link(destination, link_type), attributes) => {
+                let container = Container::Link(destination.clone(), link_type);
+                self.link(&destination, container, attributes)
+            }
+            event => Ok(event),
+        };
+
+        Some(match result {
+            Ok(event) => Ok((event, self.range.clone())),
+            Err(error) => Err(Located::new(error, self.range.clone())),
+        })
+    }
+}
+
+/// Collect the text of a math element, up to and including its end.
+///
+/// Returns the text and the offset of the end of the element.
+fn collect_math<'a>(
+    events: &mut impl Iterator<Item = (Event<'a>, Range<usize>)>,
+) -> (String, usize) {
+    let mut math = String::new();
+    let mut end = 0;
+
+    for (event, range) in events {
+        end = range.end;
+
+        match event {
+            Event::Str(str) => math.push_str(&str),
+            Event::End(_) => break,
+            _ => {}
+        }
+    }
+
+    (math, end)
+}
+
+/// The id of a display math element, if it is labelled.
+fn equation_id(attributes: &Attributes, math: &str) -> Option<String> {
+    if let Some(id) = attributes.get_value("id") {
+        return Some(id.to_string());
+    }
+
+    let (_, rest) = math.split_once("\\label{")?;
+    let (label, _) = rest.split_once('}')?;
+    Some(format!("eq:{}", label.trim()))
+}
+
+/// Remove `\label{...}` from math.
+fn strip_label(math: &str) -> String {
+    let Some((before, rest)) = math.split_once("\\label{") else {
+        return math.to_string();
+    };
+
+    match rest.split_once('}') {
+        Some((_, after)) => format!("{}{}", before, after),
+        None => math.to_string(),
+    }
+}
+
+/// The argument of `\tag{...}` or `\tag*{...}` in math, if it is tagged.
+fn existing_tag(math: &str) -> Option<&str> {
+    let (_, rest) = math.split_once("\\tag")?;
+    let rest = rest.strip_prefix('*').unwrap_or(rest).trim_start();
+    let (tag, _) = rest.strip_prefix('{')?.split_once('}')?;
+    Some(tag.trim())
+}
+
+/// Find the next `\eqref{` or `\ref{` in math.
+fn find_ref(math: &str) -> Option<(usize, &'static str)> {
+    ["\\eqref{", "\\ref{"]
+        .into_iter()
+        .filter_map(|command| Some((math.find(command)?, command)))
+        .min_by_key(|(start, _)| *start)
+}
+
+/// Error produced by [`NumberEquations`].
+#[derive(Debug, Clone, Error)]
+pub enum EquationError {
+    /// Reference to an equation that does not exist.
+    #[error("reference to unknown equation `{0}`")]
+    UnknownLabel(String),
+    /// Label of more than one equation.
+    #[error("duplicate equation label `{0}`")]
+    DuplicateLabel(String),
+    /// Reference in math without closing brace.
+    #[error("malformed equation reference in `{0}`")]
+    Malformed(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Render the document, or return the errors of [`NumberEquations`].
+    fn render(source: &str) -> Result<String, Vec<EquationError>> {
+        let events = jotdown::Parser::new(source).into_offset_iter();
+        let (events, errors): (Vec<_>, Vec<_>) =
+            NumberEquations::new(events).partition(|result| result.is_ok());
+
+        if !errors.is_empty() {
+            return Err(errors
+                .into_iter()
+                .map(|result| result.unwrap_err().error)
+                .collect());
+        }
+
+        let events = events.into_iter().map(|result| result.unwrap().0);
+        Ok(jotdown::html::render_to_string(events))
+    }
+
+    #[test]
+    fn numbers_labelled_equations_in_order() {
+        let html = render("$$`a`{#eq:a}\n\n$$`b`\n\n$$`c \\label{c}`\n").unwrap();
+
+        assert!(html.contains("id=\"eq:a\" class=\"equation"));
+        assert!(html.contains("\\[a\\tag{1}\\]"));
+        assert!(html.contains("\\[b\\]"));
+        assert!(html.contains("id=\"eq:c\" class=\"equation"));
+        assert!(html.contains("\\[c \\tag{2}\\]"));
+    }
+
+    #[test]
+    fn resolves_references_before_and_after_equations() {
+        let html = render(
+            "see [](#eq:a) and $`\\eqref{a}`\n\n$$`a`{#eq:a}\n\nthen $`x = \\eqref{a} + \\ref{a}`\n",
+        )
+        .unwrap();
+
+        assert!(html.contains("see <a href=\"#eq:a\">(1)</a> and <a href=\"#eq:a\">(1)</a>"));
+        assert!(html.contains("\\(x = \\text{(1)} + \\text{1}\\)"));
+    }
+
+    #[test]
+    fn keeps_links_with_text() {
+        let html = render("[text](#eq:a)\n\n$$`a`{#eq:a}\n").unwrap();
+
+        assert!(html.contains("<a href=\"#eq:a\">text</a>"));
+    }
+
+    #[test]
+    fn reports_unknown_labels() {
+        let errors = render("$`\\eqref{missing}`\n\n[](#eq:other)\n").unwrap_err();
+
+        assert!(matches!(
+            &errors[..],
+            [EquationError::UnknownLabel(a), EquationError::UnknownLabel(b)]
+                if a == "missing" && b == "eq:other"
+        ));
+    }
+
+    #[test]
+    fn reports_duplicate_labels() {
+        let errors = render("$$`a`{#eq:a}\n\n$$`b \\label{a}`\n").unwrap_err();
+
+        assert!(matches!(
+            &errors[..],
+            [EquationError::DuplicateLabel(id)] if id == "eq:a"
+        ));
+    }
+
+    #[test]
+    fn numbers_stay_consistent_after_duplicates() {
+        let source = "$$`a`{#eq:a}\n\n$$`b`{#eq:a}\n\n$$`c`{#eq:c}\n\n[](#eq:c)\n";
+        let events = jotdown::Parser::new(source).into_offset_iter();
+        let events = NumberEquations::new(events).filter_map(Result::ok);
+        let html = jotdown::html::render_to_string(events.map(|(event, _)| event));
+
+        assert!(html.contains("\\[c\\tag{2}\\]"));
+        assert!(html.contains("<a href=\"#eq:c\">(2)</a>"));
+    }
+
+    #[test]
+    fn keeps_existing_tags() {
+        let html =
+            render("$$`a \\tag{A}`{#eq:a}\n\n$$`b`{#eq:b}\n\n[](#eq:a) [](#eq:b)\n").unwrap();
+
+        assert!(html.contains("\\[a \\tag{A}\\]"));
+        assert!(html.contains("\\[b\\tag{1}\\]"));
+        assert!(html.contains("<a href=\"#eq:a\">(A)</a> <a href=\"#eq:b\">(1)</a>"));
+    }
+}
@@ -1,54 +1,377 @@
-use std::fmt::Display;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
 
-use jotdown::{Attributes, Container, Event};
+use jotdown::{AttributeKind, Attributes, Container, Event};
+use serde::{Deserialize, Serialize};
 use tracing::warn;
 
+use super::html::escape_html;
+
+/// An error with the byte range of the source it is about.
+///
+/// The filters in this module pair events with their ranges in the source, as
+/// produced by [`jotdown::Parser::into_offset_iter`], and report errors with
+/// the range of the element that failed.
+#[derive(Debug, Clone)]
+pub struct Located<E> {
+    pub error: E,
+    pub range: Range<usize>,
+}
+
+impl<E> Located<E> {
+    pub fn new(error: E, range: Range<usize>) -> Self {
+        Self { error, range }
+    }
+}
+
+impl<E: Display> Display for Located<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl<E: Error> Error for Located<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
+
 /// Display errors in the document.
 ///
-/// Errors are translated into a div with the `error` class. The error's
-/// [`Display`] implementation is used to generate the error message.
+/// Errors are translated into an element with the `error` class, as set by
+/// the [`ErrorStyle`]. The element is a block, unless the error occurs within
+/// inline content, e.g. in a paragraph, where it is inline. The error's
+/// [`Display`] implementation is used to generate the error message. The
+/// element gets the range of the error, which is recorded in [`Diagnostics`]
+/// if given, and logged otherwise.
 #[derive(Debug, Clone)]
 pub struct ShowErrors<'a, I> {
     inner: I,
-    buffer: Vec<Event<'a>>,
+    style: ErrorStyle,
+    source: Option<&'a str>,
+    diagnostics: Option<Diagnostics>,
+    /// The number of open containers with inline content.
+    inline: usize,
+    buffer: Vec<(Event<'a>, Range<usize>)>,
 }
 
 impl<'a, I> ShowErrors<'a, I> {
     pub fn new(inner: I) -> Self {
         Self {
             inner,
+            style: ErrorStyle::default(),
+            source: None,
+            diagnostics: None,
+            inline: 0,
             buffer: Vec::with_capacity(2),
         }
     }
+
+    /// Set how errors are presented.
+    pub fn with_style(mut self, style: ErrorStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Set the source that the ranges of the events refer to, so that
+    /// [`ErrorStyle::Details`] can show the source of the failing element.
+    pub fn with_source(mut self, source: &'a str) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Record the errors in a shared record instead of logging them.
+    pub fn with_diagnostics(mut self, diagnostics: Diagnostics) -> Self {
+        self.diagnostics = Some(diagnostics);
+        self
+    }
+
+    /// Keep track of whether the next element is inline.
+    fn track(&mut self, event: &Event) {
+        match event {
+            Event::Start(container, _) if !container.is_block_container() => self.inline += 1,
+            Event::End(container) if !container.is_block_container() => {
+                self.inline = self.inline.saturating_sub(1)
+            }
+            _ => {}
+        }
+    }
+
+    /// A collapsible block with the message, the source and the causes of an
+    /// error.
+    fn details(&self, error: &dyn Error, range: Range<usize>) -> String {
+        let mut html = format!(
+            "<details class=\"error\">\n<summary>{}</summary>\n",
+            escape_html(&error.to_string())
+        );
+
+        if let Some(source) = self.source.and_then(|source| source.get(range)) {
+            html.push_str(&format!(
+                "<pre><code>{}</code></pre>\n",
+                escape_html(source.trim_end())
+            ));
+        }
+
+        let mut cause = error.source();
+
+        if cause.is_some() {
+            html.push_str("<ol class=\"error-chain\">\n");
+
+            while let Some(error) = cause {
+                html.push_str(&format!("<li>{}</li>\n", escape_html(&error.to_string())));
+                cause = error.source();
+            }
+
+            html.push_str("</ol>\n");
+        }
+
+        html.push_str("</details>");
+        html
+    }
+
+    /// Like [`Self::details`], for errors within inline content.
+    ///
+    /// The message is followed by a span with the `error-details` class, e.g.
+    /// to be shown when the error is hovered or focused.
+    fn inline_details(&self, error: &dyn Error, range: Range<usize>) -> String {
+        let mut html = format!(
+            "<span class=\"error\" tabindex=\"0\"><span class=\"error-summary\">{}</span><span class=\"error-details\">",
+            escape_html(&error.to_string())
+        );
+
+        if let Some(source) = self.source.and_then(|source| source.get(range)) {
+            html.push_str(&format!(" <code>{}</code>", escape_html(source.trim())));
+        }
+
+        let mut cause = error.source();
+
+        if cause.is_some() {
+            html.push_str(" <span class=\"error-chain\">");
+
+            while let Some(error) = cause {
+                html.push_str(&format!("<span>{}</span>", escape_html(&error.to_string())));
+                cause = error.source();
+            }
+
+            html.push_str("</span>");
+        }
+
+        html.push_str("</span></span>");
+        html
+    }
 }
 
+/// How [`ShowErrors`] presents errors in the document.
+///
+/// Within inline content, the divs are spans instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorStyle {
+    /// A div with the error message.
+    #[default]
+    Message,
+    /// A collapsible `details` block with the error message, the source of the
+    /// failing element and the chain of errors that caused it, or a span with
+    /// the same within inline content.
+    Details,
+    /// A div with a neutral message, which does not reveal the error.
+    Placeholder,
+}
+
+/// The message of [`ErrorStyle::Placeholder`].
+const PLACEHOLDER: &str = "This content could not be rendered.";
+
 impl<'a, I, E> Iterator for ShowErrors<'a, I>
 where
-    I: Iterator<Item = Result<Event<'a>, E>>,
-    E: Display,
+    I: Iterator<Item = Result<(Event<'a>, Range<usize>), Located<E>>>,
+    E: Error,
 {
-    type Item = Event<'a>;
+    type Item = (Event<'a>, Range<usize>);
 
     fn next(&mut self) -> Option<Self::Item> {
         if let Some(event) = self.buffer.pop() {
             return Some(event);
         }
 
-        let error = match self.inner.next()? {
-            Ok(event) => return Some(event),
+        let Located { error, range } = match self.inner.next()? {
+            Ok(event) => {
+                self.track(&event.0);
+                return Some(event);
+            }
             Err(error) => error,
         };
 
-        warn!("{}", error);
+        let message = error.to_string();
+
+        match &self.diagnostics {
+            Some(diagnostics) => diagnostics.record(Severity::Error, &message, range.clone()),
+            None => warn!("{}", message),
+        }
+
+        let inline = self.inline > 0;
+
+        let content = match self.style {
+            ErrorStyle::Message => message,
+            ErrorStyle::Details if inline => self.inline_details(&error, range.clone()),
+            ErrorStyle::Details => self.details(&error, range.clone()),
+            ErrorStyle::Placeholder => PLACEHOLDER.into(),
+        };
+
+        let mut attributes = Attributes::new();
+
+        let container = match (self.style, inline) {
+            (ErrorStyle::Details, true) => Container::RawInline { format: "html" },
+            (ErrorStyle::Details, false) => Container::RawBlock { format: "html" },
+            (_, true) => {
+                attributes.push((AttributeKind::Class, "error".into()));
+                Container::Span
+            }
+            (_, false) => Container::Div { class: "error" },
+        };
 
         self.buffer.extend([
-            Event::End(Container::Div { class: "error" }),
-            Event::Str(error.to_string().into()),
+            (Event::End(container.clone()), range.clone()),
+            (Event::Str(content.into()), range.clone()),
         ]);
 
-        Some(Event::Start(
-            Container::Div { class: "error" },
-            Attributes::new(),
-        ))
+        Some((Event::Start(container, attributes), range))
+    }
+}
+
+/// An error shown in a document by [`ShowErrors`], or a warning of a filter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// The byte range of the source the diagnostic is about.
+    pub range: Range<usize>,
+}
+
+/// How severe a [`Diagnostic`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// An element failed and was replaced by an error.
+    Error,
+    /// An element was rendered, but not as intended.
+    Warning,
+}
+
+/// Errors shown in a document by [`ShowErrors`], and warnings of filters.
+///
+/// The record can be cloned to share it between the filters of a document,
+/// e.g. to report all its errors with their positions after rendering.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics(Arc<Mutex<Vec<Diagnostic>>>);
+
+impl Diagnostics {
+    /// Create an empty record.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn record(&self, severity: Severity, message: &str, range: Range<usize>) {
+        let mut diagnostics = self.0.lock().unwrap();
+        diagnostics.push(Diagnostic {
+            severity,
+            message: message.to_string(),
+            range,
+        });
+    }
+
+    /// Record a warning about the byte `range` of the source.
+    pub fn warn(&self, message: &str, range: Range<usize>) {
+        self.record(Severity::Warning, message, range);
+    }
+
+    /// The recorded errors, in the order of their position in the source.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.0.lock().unwrap().clone();
+        diagnostics.sort_by_key(|diagnostic| diagnostic.range.start);
+        diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("failed <here>")]
+    struct TestError;
+
+    /// Render the source, with verbatim and code blocks failing like the
+    /// filters do, with the range of the whole element.
+    fn render(source: &str, style: ErrorStyle) -> String {
+        let mut events = Vec::new();
+        let mut failing = false;
+
+        for (event, range) in jotdown::Parser::new(source).into_offset_iter() {
+            match event {
+                Event::Start(Container::Verbatim | Container::CodeBlock { .. }, _) => {
+                    events.push(Err(Located::new(TestError, range)));
+                    failing = true;
+                }
+                Event::End(Container::Verbatim | Container::CodeBlock { .. }) => {
+                    if let Some(Err(error)) = events.last_mut() {
+                        error.range.end = range.end;
+                    }
+
+                    failing = false;
+                }
+                _ if failing => {}
+                event => events.push(Ok((event, range))),
+            }
+        }
+
+        let events = ShowErrors::new(events.into_iter())
+            .with_style(style)
+            .with_source(source);
+        jotdown::html::render_to_string(events.map(|(event, _)| event))
+    }
+
+    #[test]
+    fn inline_errors_are_spans() {
+        assert_eq!(
+            render("a `x` b\n", ErrorStyle::Message),
+            "<p>a <span class=\"error\">failed &lt;here&gt;</span> b</p>\n"
+        );
+        assert_eq!(
+            render("# a `x`\n", ErrorStyle::Placeholder),
+            format!(
+                "<section id=\"a-x\">\n<h1>a <span class=\"error\">{}</span></h1>\n</section>\n",
+                PLACEHOLDER
+            )
+        );
+    }
+
+    #[test]
+    fn block_errors_are_divs() {
+        assert_eq!(
+            render("> ```\n> x\n> ```\n", ErrorStyle::Message),
+            "<blockquote>\n<div class=\"error\">failed &lt;here&gt;\n</div>\n</blockquote>\n"
+        );
+    }
+
+    #[test]
+    fn inline_details_are_spans() {
+        let html = render("a `x` b\n", ErrorStyle::Details);
+
+        assert!(html.starts_with("<p>a <span class=\"error\" tabindex=\"0\">"));
+        assert!(html.contains("<span class=\"error-summary\">failed &lt;here&gt;</span>"));
+        assert!(html.contains("<code>`x`</code>"));
+        assert!(html.ends_with("</span></span> b</p>\n"));
+        assert!(!html.contains("<details"));
+    }
+
+    #[test]
+    fn block_details_are_details() {
+        let html = render("```\nx\n```\n\npara\n", ErrorStyle::Details);
+
+        assert!(
+            html.starts_with("<details class=\"error\">\n<summary>failed &lt;here&gt;</summary>")
+        );
+        assert!(html.ends_with("</details>\n<p>para</p>\n"));
     }
 }
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::time::Duration;
+
+use jotdown::{AttributeKind, AttributeValue, Attributes, Container, Event};
+use thiserror::Error;
+use tracing::{debug, trace};
+
+use super::error::Located;
+use crate::cache::{Cache, cache_key};
+use crate::tools::ToolError;
+use crate::tools::exec::{ExecOutput, Interpreter};
+
+/// Run code blocks marked with `exec=true` and show their output.
+///
+/// The code is run by the interpreter for the language of the code block, in
+/// an empty temporary directory. The code block is kept as is, and followed by
+/// code blocks with the `output` class for the output of the program: one for
+/// stdout, and one with the `stderr` class for stderr and the exit status if
+/// the program failed. The output blocks are in [`DEFAULT_OUTPUT_LANGUAGE`]
+/// unless set otherwise, so that they are highlighted like other code.
+#[derive(Debug)]
+pub struct ExecCode<'a, I> {
+    inner: I,
+    interpreters: HashMap<String, Interpreter>,
+    timeout: Duration,
+    output_language: &'a str,
+    cache: Option<Cache<ExecOutput>>,
+    buffer: Vec<Result<Event<'a>, ExecCodeError>>,
+    /// The range of the code block that the buffered events come from.
+    range: Range<usize>,
+}
+
+impl<'a, I> ExecCode<'a, I> {
+    /// Create a filter that runs code blocks with the interpreters for their
+    /// languages.
+    pub fn new(inner: I, interpreters: HashMap<String, Interpreter>) -> Self {
+        Self {
+            inner,
+            interpreters,
+            timeout: DEFAULT_TIMEOUT,
+            output_language: DEFAULT_OUTPUT_LANGUAGE,
+            cache: None,
+            buffer: Vec::new(),
+            range: 0..0,
+        }
+    }
+
+    /// Kill programs that do not finish within `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the language of the output blocks, e.g. `console`.
+    pub fn with_output_language(mut self, language: &'a str) -> Self {
+        self.output_language = language;
+        self
+    }
+
+    /// Reuse outputs from the given cache.
+    pub fn with_cache(mut self, cache: Cache<ExecOutput>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    fn run(&self, language: &str, code: &str) -> Result<ExecOutput, ExecCodeError> {
+        let Some(interpreter) = self.interpreters.get(language) else {
+            return Err(ExecCodeError::NoInterpreter(language.to_string()));
+        };
+
+        let key = cache_key((language, interpreter, code));
+
+        if let Some(output) = self.cache.as_ref().and_then(|cache| cache.get(&key)) {
+            trace!("using cached output");
+            return Ok(output);
+        }
+
+        debug!("running {} code block", language);
+        let output = interpreter.run_blocking(code, self.timeout)?;
+
+        if let Some(cache) = &self.cache {
+            cache.insert(key, output.clone());
+        }
+
+        Ok(output)
+    }
+
+    /// Queue an output block with the given text.
+    fn push_output(&mut self, classes: &'static str, text: String) {
+        let container = Container::CodeBlock {
+            language: self.output_language,
+        };
+        let mut attributes = Attributes::new();
+        attributes.push((AttributeKind::Class, AttributeValue::from(classes)));
+
+        // The buffer is a stack, so the events are pushed in reverse.
+        self.buffer.splice(
+            0..0,
+            [
+                Ok(Event::End(container.clone())),
+                Ok(Event::Str(text.into())),
+                Ok(Event::Start(container, attributes)),
+            ],
+        );
+    }
+}
+
+/// The default timeout for programs.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The default language of output blocks, which Inkjet renders as plain text.
+pub const DEFAULT_OUTPUT_LANGUAGE: &str = "plaintext";
+
+impl<'a, I> Iterator for ExecCode<'a, I>
+where
+    I: Iterator<Item = (Event<'a>, Range<usize>)>,
+{
+    type Item = Result<(Event<'a>, Range<usize>), Located<ExecCodeError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(result) = self.buffer.pop() {
+            return Some(match result {
+                Ok(event) => Ok((event, self.range.clone())),
+                Err(err) => Err(Located::new(err, self.range.clone())),
+            });
+        }
+
+        let (language, mut attributes) = match self.inner.next()? {
+            (Event::Start(Container::CodeBlock { language }, attributes), range)
+                if attributes.contains_key("exec") =>
+            {
+                self.range = range;
+                (language, attributes)
+            }
+            event => return Some(Ok(event)),
+        };
+
+        let exec = attributes.get_value("exec").unwrap().to_string();
+        attributes.retain(|(kind, _)| !matches!(kind, AttributeKind::Pair { key: "exec" }));
+
+        let mut code = String::new();
+
+        for (event, range) in self.inner.by_ref() {
+            self.range.end = range.end;
+
+            match event {
+                Event::End(_) => break,
+                Event::Str(str) => code.push_str(&str),
+                _ => {
+                    let range = self.range.clone();
+                    return Some(Err(Located::new(ExecCodeError::Unexpected, range)));
+                }
+            }
+        }
+
+        let container = Container::CodeBlock { language };
+        self.buffer.extend([
+            Ok(Event::End(container.clone())),
+            Ok(Event::Str(code.clone().into())),
+        ]);
+
+        let result = match exec.as_str() {
+            "true" => self.run(language, &code),
+            "false" => {
+                let start = Event::Start(container, attributes);
+                return Some(Ok((start, self.range.clone())));
+            }
+            _ => Err(ExecCodeError::Attribute(exec)),
+        };
+
+        match result {
+            Ok(output) => {
+                let mut stderr = output.stderr.clone();
+
+                match output.status {
+                    Some(0) => {}
+                    Some(status) => stderr.push_str(&format!("[exit status {}]\n", status)),
+                    None => stderr.push_str("[terminated by signal]\n"),
+                }
+
+                if !output.stdout.is_empty() {
+                    self.push_output("output", output.stdout);
+                }
+
+                if !stderr.is_empty() {
+                    self.push_output("output stderr", stderr);
+                }
+            }
+            Err(err) => self.buffer.insert(0, Err(err)),
+        }
+
+        Some(Ok((
+            Event::Start(container, attributes),
+            self.range.clone(),
+        )))
+    }
+}
+
+/// Error produced by [`ExecCode`].
+#[derive(Debug, Error)]
+pub enum ExecCodeError {
+    /// No interpreter is configured for the language of a code block.
+    #[error("no interpreter for code blocks in `{0}`")]
+    NoInterpreter(String),
+    /// Error while running a code block.
+    #[error("failed to run code block: {0}")]
+    Exec(#[from] ToolError),
+    /// Invalid value of the `exec` attribute.
+    #[error("invalid value `{0}` for code block attribute `exec`")]
+    Attribute(String),
+    /// Unexpected [`Event`] in code block.
+    #[error("unexpected event in code block")]
+    Unexpected,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interpreters(command: &str) -> HashMap<String, Interpreter> {
+        let interpreter = Interpreter {
+            command: vec![command.into()],
+            extension: None,
+        };
+        HashMap::from([("sh".to_string(), interpreter)])
+    }
+
+    fn render<'a>(exec: ExecCode<'a, impl Iterator<Item = (Event<'a>, Range<usize>)>>) -> String {
+        jotdown::html::render_to_string(exec.map(|result| result.unwrap().0))
+    }
+
+    fn exec(source: &str) -> ExecCode<'_, jotdown::OffsetIter<'_>> {
+        let events = jotdown::Parser::new(source).into_offset_iter();
+        ExecCode::new(events, interpreters("sh"))
+    }
+
+    #[test]
+    fn stdout_and_stderr() {
+        let html = render(exec("{exec=true}\n``` sh\necho out\necho err >&2\n```\n"));
+
+        assert_eq!(
+            html,
+            "<pre><code class=\"language-sh\">echo out\necho err &gt;&amp;2\n</code></pre>\n\
+             <pre class=\"output\"><code class=\"language-plaintext\">out\n</code></pre>\n\
+             <pre class=\"output stderr\"><code class=\"language-plaintext\">err\n</code></pre>\n"
+        );
+    }
+
+    #[test]
+    fn exit_status() {
+        let html = render(exec("{exec=true}\n``` sh\nexit 2\n```\n"));
+
+        assert!(html.ends_with(
+            "<pre class=\"output stderr\"><code class=\"language-plaintext\">\
+             [exit status 2]\n</code></pre>\n"
+        ));
+        assert!(!html.contains("class=\"output\""));
+    }
+
+    #[test]
+    fn timeout() {
+        let source = "{exec=true}\n``` sh\nsleep 10\n```\n";
+        let mut events = exec(source).with_timeout(Duration::from_millis(100));
+
+        let error = events.find_map(Result::err).unwrap();
+        assert!(matches!(
+            error.error,
+            ExecCodeError::Exec(ToolError::Timeout { .. })
+        ));
+        assert_eq!(error.range, 0..source.len());
+    }
+
+    #[test]
+    fn cached_output() {
+        let code = "echo out\n";
+        let cache = Cache::new();
+        let interpreters = interpreters("scribe-missing-interpreter");
+        let key = cache_key(("sh", &interpreters["sh"], code));
+        let output = ExecOutput {
+            stdout: "cached\n".into(),
+            stderr: String::new(),
+            status: Some(0),
+        };
+        cache.insert(key, output);
+
+        // The interpreter is missing, so the output can only come from the cache.
+        let source = format!("{{exec=true}}\n``` sh\n{}```\n", code);
+        let events = jotdown::Parser::new(&source).into_offset_iter();
+        let html = render(ExecCode::new(events, interpreters).with_cache(cache));
+
+        assert!(html.contains("<code class=\"language-plaintext\">cached\n</code>"));
+    }
+
+    #[test]
+    fn output_language() {
+        let source = "{exec=true}\n``` sh\necho out\n```\n";
+
+        assert!(render(exec(source)).contains(&format!(
+            "<code class=\"language-{}\">out\n",
+            DEFAULT_OUTPUT_LANGUAGE
+        )));
+        assert!(
+            render(exec(source).with_output_language("console"))
+                .contains("<code class=\"language-console\">out\n")
+        );
+    }
+
+    #[test]
+    fn exec_false_is_unmodified() {
+        assert_eq!(
+            render(exec("{exec=false}\n``` sh\necho out\n```\n")),
+            "<pre><code class=\"language-sh\">echo out\n</code></pre>\n"
+        );
+    }
+}
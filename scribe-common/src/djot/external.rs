@@ -0,0 +1,149 @@
+use std::ops::Range;
+use std::time::Duration;
+
+use jotdown::Event;
+use thiserror::Error;
+use tracing::debug;
+
+use super::error::Located;
+use super::json::{events_from_json, events_to_json};
+use super::strings::Strings;
+use crate::tools::ToolError;
+use crate::tools::filter::FilterCommand;
+
+/// An event or error of [`ExternalFilter`].
+type Output<'a> = Result<(Event<'a>, Range<usize>), Located<ExternalFilterError>>;
+
+/// Transform the events of a document with an external command.
+///
+/// The command gets the events of the whole document as JSON on stdin, see
+/// [`events_to_json`], and writes the transformed events in the same format
+/// to stdout. If the command fails, the events are left unmodified. Strings
+/// that the transformed events borrow are stored in `strings`.
+#[derive(Debug)]
+pub struct ExternalFilter<'a, I> {
+    inner: I,
+    command: FilterCommand,
+    strings: &'a Strings,
+    timeout: Duration,
+    output: Option<std::vec::IntoIter<Output<'a>>>,
+}
+
+impl<'a, I> ExternalFilter<'a, I> {
+    pub fn new(inner: I, command: FilterCommand, strings: &'a Strings) -> Self {
+        Self {
+            inner,
+            command,
+            strings,
+            timeout: DEFAULT_TIMEOUT,
+            output: None,
+        }
+    }
+
+    /// Kill the command if it does not finish within `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn transform(
+        &self,
+        events: &[(Event<'a>, Range<usize>)],
+    ) -> Result<Vec<(Event<'a>, Range<usize>)>, ExternalFilterError> {
+        debug!(
+            "running external filter `{}`",
+            self.command.command.join(" ")
+        );
+
+        let input = events_to_json(events)?;
+        let output = self.command.run_blocking(&input, self.timeout)?;
+        Ok(events_from_json(&output, self.strings)?)
+    }
+}
+
+/// The default timeout for the command.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl<'a, I> Iterator for ExternalFilter<'a, I>
+where
+    I: Iterator<Item = (Event<'a>, Range<usize>)>,
+{
+    type Item = Output<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(output) = &mut self.output {
+            return output.next();
+        }
+
+        let events: Vec<_> = self.inner.by_ref().collect();
+
+        let output: Vec<_> = match self.transform(&events) {
+            Ok(output) => output.into_iter().map(Ok).collect(),
+            Err(err) => {
+                // The error is shown at the start of the document.
+                let start = events.first().map_or(0, |(_, range)| range.start);
+                let error = Located::new(err, start..start);
+                std::iter::once(Err(error))
+                    .chain(events.into_iter().map(Ok))
+                    .collect()
+            }
+        };
+
+        self.output.insert(output.into_iter()).next()
+    }
+}
+
+/// Error produced by [`ExternalFilter`].
+#[derive(Debug, Error)]
+pub enum ExternalFilterError {
+    /// Error while running the command.
+    #[error("external filter failed: {0}")]
+    Command(#[from] ToolError),
+    /// Events that the command wrote or is to read are invalid.
+    #[error("invalid events for external filter: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(command: &[&str]) -> FilterCommand {
+        FilterCommand {
+            command: command.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+
+    const SOURCE: &str =
+        "::: note\n*a*{key=value} `b`{=html}\n:::\n\n``` rust\nfn main() {}\n```\n";
+
+    #[test]
+    fn round_trip_through_cat() {
+        let strings = Strings::new();
+        let events: Vec<_> = jotdown::Parser::new(SOURCE).into_offset_iter().collect();
+
+        let output: Vec<_> =
+            ExternalFilter::new(events.clone().into_iter(), filter(&["cat"]), &strings)
+                .map(Result::unwrap)
+                .collect();
+
+        assert_eq!(output, events);
+    }
+
+    #[test]
+    fn failure_keeps_events() {
+        let strings = Strings::new();
+        let events: Vec<_> = jotdown::Parser::new(SOURCE).into_offset_iter().collect();
+
+        let mut output =
+            ExternalFilter::new(events.clone().into_iter(), filter(&["false"]), &strings);
+
+        let error = output.next().unwrap().unwrap_err();
+        assert!(matches!(
+            error.error,
+            ExternalFilterError::Command(ToolError::Failed { .. })
+        ));
+        assert_eq!(error.range, 0..0);
+        assert_eq!(output.map(Result::unwrap).collect::<Vec<_>>(), events);
+    }
+}
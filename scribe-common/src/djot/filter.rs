@@ -0,0 +1,294 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use jotdown::Event;
+use serde::de::Error as _;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+/// Events of a document with their ranges in the source.
+pub type Events<'a> = Box<dyn Iterator<Item = (Event<'a>, Range<usize>)> + 'a>;
+
+/// A step of a [`Pipeline`], which transforms the events of a document.
+///
+/// Filters that can fail are expected to show their errors in the document,
+/// e.g. with [`ShowErrors`](super::ShowErrors). Closures taking and returning
+/// [`Events`] are filters.
+pub trait Filter<'a> {
+    /// Set the options of the filter, as given by a [`PipelineConfig`].
+    ///
+    /// Filters have no options unless they override this.
+    fn configure(&mut self, options: Value) -> Result<(), serde_json::Error> {
+        let _ = options;
+        Err(serde_json::Error::custom("the filter has no options"))
+    }
+
+    fn apply(self: Box<Self>, events: Events<'a>) -> Events<'a>;
+}
+
+impl<'a, F> Filter<'a> for F
+where
+    F: FnOnce(Events<'a>) -> Events<'a>,
+{
+    fn apply(self: Box<Self>, events: Events<'a>) -> Events<'a> {
+        (*self)(events)
+    }
+}
+
+/// Filters that are applied to a document in order.
+///
+/// Filters are registered by name, so that they can be reordered and disabled
+/// by a [`PipelineConfig`].
+#[derive(Default)]
+pub struct Pipeline<'a> {
+    filters: Vec<(String, Box<dyn Filter<'a> + 'a>)>,
+}
+
+impl<'a> Pipeline<'a> {
+    /// Create an empty pipeline.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a filter at the end, or replace the filter with the same name.
+    pub fn with_filter(mut self, name: impl Into<String>, filter: impl Filter<'a> + 'a) -> Self {
+        let name = name.into();
+        let filter: Box<dyn Filter<'a> + 'a> = Box::new(filter);
+
+        match self.filters.iter_mut().find(|(other, _)| *other == name) {
+            Some((_, existing)) => *existing = filter,
+            None => self.filters.push((name, filter)),
+        }
+
+        self
+    }
+
+    /// Reorder, disable and set the options of the filters as configured.
+    pub fn configure(mut self, config: &PipelineConfig) -> Result<Self, PipelineError> {
+        let names = config
+            .order
+            .iter()
+            .flatten()
+            .chain(config.enabled.keys())
+            .chain(config.options.keys());
+
+        for name in names {
+            if !self.filters.iter().any(|(other, _)| other == name) {
+                return Err(PipelineError::UnknownFilter(name.clone()));
+            }
+        }
+
+        if let Some(order) = &config.order {
+            let mut filters = std::mem::take(&mut self.filters);
+
+            for name in order {
+                if let Some(index) = filters.iter().position(|(other, _)| other == name) {
+                    self.filters.push(filters.remove(index));
+                }
+            }
+        }
+
+        self.filters
+            .retain(|(name, _)| config.enabled.get(name).copied().unwrap_or(true));
+
+        for (name, filter) in &mut self.filters {
+            if let Some(options) = config.options.get(name) {
+                filter
+                    .configure(options.clone())
+                    .map_err(|error| PipelineError::Options {
+                        filter: name.clone(),
+                        message: error.to_string(),
+                    })?;
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// The names of the filters, in order.
+    pub fn names(&self) -> Vec<&str> {
+        self.filters.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// Apply the filters to the events of a document.
+    pub fn apply(self, events: Events<'a>) -> Events<'a> {
+        self.filters
+            .into_iter()
+            .fold(events, |events, (_, filter)| filter.apply(events))
+    }
+}
+
+/// Configuration of a [`Pipeline`].
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct PipelineConfig {
+    /// The filters to apply, in order. Defaults to all filters in the order
+    /// they are registered in.
+    #[serde(default)]
+    pub order: Option<Vec<String>>,
+    /// Whether filters are enabled, e.g. `highlight = false`. Filters are
+    /// enabled by default.
+    #[serde(default)]
+    pub enabled: BTreeMap<String, bool>,
+    /// Options of the filters that have them, by filter, e.g.
+    /// `headings = { offset = 2 }`.
+    #[serde(default)]
+    pub options: BTreeMap<String, Value>,
+}
+
+impl PipelineConfig {
+    /// Combine with the `defaults`, taking the settings of `self` where both
+    /// have them.
+    pub fn or(&self, defaults: &Self) -> Self {
+        let mut enabled = defaults.enabled.clone();
+        enabled.extend(self.enabled.clone());
+
+        // Options are combined option by option.
+        let mut options = defaults.options.clone();
+
+        for (name, value) in &self.options {
+            match (options.get_mut(name), value) {
+                (Some(Value::Object(options)), Value::Object(value)) => {
+                    options.extend(value.clone());
+                }
+                _ => {
+                    options.insert(name.clone(), value.clone());
+                }
+            }
+        }
+
+        Self {
+            order: self.order.clone().or_else(|| defaults.order.clone()),
+            enabled,
+            options,
+        }
+    }
+}
+
+/// Error produced while configuring a [`Pipeline`].
+#[derive(Debug, Clone, Error)]
+pub enum PipelineError {
+    /// Configuration of a filter that is not registered.
+    #[error("unknown filter `{0}`")]
+    UnknownFilter(String),
+    /// Invalid options of a filter.
+    #[error("invalid options for filter `{filter}`: {message}")]
+    Options { filter: String, message: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::djot::DemoteHeadingsFilter;
+
+    fn pipeline() -> Pipeline<'static> {
+        ["a", "b", "c"]
+            .into_iter()
+            .fold(Pipeline::new(), |pipeline, name| {
+                pipeline.with_filter(name, |events: Events<'static>| events)
+            })
+    }
+
+    fn config(source: &str) -> PipelineConfig {
+        toml::from_str(source).unwrap()
+    }
+
+    #[test]
+    fn filters_in_registration_order() {
+        let pipeline = pipeline().configure(&PipelineConfig::default()).unwrap();
+        assert_eq!(pipeline.names(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn replace_filter_in_place() {
+        let pipeline = pipeline().with_filter("a", |events: Events<'static>| events);
+        assert_eq!(pipeline.names(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn order_selects_filters() {
+        let pipeline = pipeline().configure(&config("order = ['c', 'a']")).unwrap();
+        assert_eq!(pipeline.names(), ["c", "a"]);
+    }
+
+    #[test]
+    fn disable_filters() {
+        let config = config("order = ['c', 'b', 'a']\nenabled = { b = false, c = true }");
+        let pipeline = pipeline().configure(&config).unwrap();
+        assert_eq!(pipeline.names(), ["c", "a"]);
+    }
+
+    #[test]
+    fn reject_unknown_filters() {
+        for source in [
+            "order = ['a', 'd']",
+            "enabled = { d = false }",
+            "options.d = {}",
+        ] {
+            let result = pipeline().configure(&config(source));
+            assert!(matches!(result, Err(PipelineError::UnknownFilter(name)) if name == "d"));
+        }
+    }
+
+    #[test]
+    fn note_config_takes_precedence() {
+        let site = config("order = ['a', 'b']\nenabled = { a = false, b = false }");
+        let note = config("enabled = { b = true }");
+
+        let combined = note.or(&site);
+        assert_eq!(combined.order, site.order);
+        assert_eq!(
+            combined.enabled,
+            [("a".into(), false), ("b".into(), true)].into()
+        );
+
+        let note = config("order = ['b']");
+        assert_eq!(note.or(&site).order, note.order);
+    }
+
+    #[test]
+    fn combine_options() {
+        let site = config("options.h = { offset = 2, other = true }");
+        let note = config("options.h = { offset = 0 }");
+
+        assert_eq!(
+            note.or(&site).options["h"],
+            serde_json::json!({ "offset": 0, "other": true })
+        );
+    }
+
+    #[test]
+    fn configure_options() {
+        let render = |config: &str| {
+            let pipeline = Pipeline::new()
+                .with_filter("headings", DemoteHeadingsFilter::new(1))
+                .configure(&self::config(config))
+                .unwrap();
+            let events = jotdown::Parser::new("# a\n").into_offset_iter();
+            let events = pipeline.apply(Box::new(events));
+            jotdown::html::render_to_string(events.map(|(event, _)| event))
+        };
+
+        assert!(render("").contains("<h2>a</h2>"));
+        assert!(render("options.headings = { offset = 2 }").contains("<h3>a</h3>"));
+    }
+
+    #[test]
+    fn reject_invalid_options() {
+        for source in [
+            "options.headings = { offset = -1 }",
+            "options.headings = { x = 1 }",
+        ] {
+            let result = Pipeline::new()
+                .with_filter("headings", DemoteHeadingsFilter::new(1))
+                .configure(&config(source));
+            assert!(
+                matches!(result, Err(PipelineError::Options { filter, .. }) if filter == "headings")
+            );
+        }
+
+        // Closures have no options.
+        let result = pipeline().configure(&config("options.a = { x = 1 }"));
+        assert!(matches!(result, Err(PipelineError::Options { filter, .. }) if filter == "a"));
+    }
+}
@@ -1,33 +1,409 @@
+use std::error::Error;
+
 use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
 use thiserror::Error;
 
-/// Split off the frontmatter string, if any.
-pub fn split_frontmatter<'a>(source: &'a str) -> (Option<&'a str>, &'a str) {
-    let split = source
-        .strip_prefix("---\n")
-        .and_then(|rest| rest.split_once("\n---\n"));
+/// The byte order mark, which some editors put at the start of a file.
+const BOM: &str = "\u{feff}";
+
+/// The format of a frontmatter block, given by its fences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrontmatterFormat {
+    /// YAML between `---` lines.
+    Yaml,
+    /// TOML between `+++` lines.
+    Toml,
+}
+
+impl FrontmatterFormat {
+    fn from_fence(fence: &str) -> Option<Self> {
+        match fence {
+            "---" => Some(Self::Yaml),
+            "+++" => Some(Self::Toml),
+            _ => None,
+        }
+    }
+}
+
+/// A frontmatter block of a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frontmatter<'a> {
+    pub format: FrontmatterFormat,
+    /// The content between the fences.
+    pub content: &'a str,
+    /// The byte offset of the content in the source.
+    pub offset: usize,
+}
+
+/// A document split into its frontmatter and body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SplitFrontmatter<'a> {
+    pub frontmatter: Option<Frontmatter<'a>>,
+    pub body: &'a str,
+    /// The byte offset of the body in the source.
+    pub body_offset: usize,
+}
+
+/// Split off the frontmatter block, if any.
+///
+/// The block is YAML between `---` lines or TOML between `+++` lines, at the
+/// start of the document. A byte order mark, trailing whitespace on the fences
+/// and CRLF line endings are allowed, and the closing fence can end the
+/// document. Without a closing fence, the document has no frontmatter.
+pub fn split_frontmatter(source: &str) -> SplitFrontmatter<'_> {
+    let start = match source.starts_with(BOM) {
+        true => BOM.len(),
+        false => 0,
+    };
+
+    let no_frontmatter = SplitFrontmatter {
+        frontmatter: None,
+        body: &source[start..],
+        body_offset: start,
+    };
+
+    let mut lines = source[start..].split_inclusive('\n');
+
+    let Some(format) = lines.next().and_then(fence) else {
+        return no_frontmatter;
+    };
 
-    match split {
-        Some((frontmatter, body)) => (Some(frontmatter), body),
-        None => (None, source),
+    let content_start = start + source[start..].find('\n').map_or(0, |index| index + 1);
+    let mut line_start = content_start;
+
+    for line in lines {
+        if fence(line) == Some(format) {
+            let body_offset = line_start + line.len();
+
+            return SplitFrontmatter {
+                frontmatter: Some(Frontmatter {
+                    format,
+                    content: &source[content_start..line_start],
+                    offset: content_start,
+                }),
+                body: &source[body_offset..],
+                body_offset,
+            };
+        }
+
+        line_start += line.len();
     }
+
+    no_frontmatter
+}
+
+/// The format of a fence line, if it is one.
+fn fence(line: &str) -> Option<FrontmatterFormat> {
+    FrontmatterFormat::from_fence(line.trim_end_matches([' ', '\t', '\r', '\n']))
 }
 
-/// Parse the YAML frontmatter string, if any.
+/// Parse the frontmatter, if any, returning it with the body.
 pub fn parse_frontmatter<'a, T>(source: &'a str) -> Result<(T, &'a str), FrontmatterError>
 where
     T: Default + Deserialize<'a>,
 {
-    let (frontmatter, body) = split_frontmatter(source);
+    let split = split_frontmatter(source);
+
+    let Some(frontmatter) = split.frontmatter else {
+        return Ok((T::default(), split.body));
+    };
 
-    let frontmatter = match frontmatter {
-        Some(frontmatter) => serde_yaml_ng::from_str(frontmatter).map_err(FrontmatterError)?,
-        None => T::default(),
+    let content = frontmatter.content;
+
+    let value = match frontmatter.format {
+        FrontmatterFormat::Yaml => serde_yaml_ng::from_str(content).map_err(|source| {
+            let offset = yaml_error_offset(content, &source);
+            FrontmatterError {
+                offset: offset.map(|offset| frontmatter.offset + offset),
+                source: source.into(),
+            }
+        })?,
+        FrontmatterFormat::Toml => {
+            let toml_error = |source: toml::de::Error| FrontmatterError {
+                offset: source.span().map(|span| frontmatter.offset + span.start),
+                // The message, without the snippet of the source.
+                source: source.message().into(),
+            };
+            let table: toml::Table = toml::from_str(content).map_err(toml_error)?;
+            T::deserialize(datetimes_to_strings(toml::Value::Table(table))).map_err(toml_error)?
+        }
     };
 
+    Ok((value, split.body))
+}
+
+/// Replace TOML datetimes with strings, e.g. `2024-01-01`, like YAML dates.
+///
+/// Deserialized directly, datetimes are maps with a private key, which fields
+/// expecting strings reject.
+fn datetimes_to_strings(value: toml::Value) -> toml::Value {
+    match value {
+        toml::Value::Datetime(datetime) => toml::Value::String(datetime.to_string()),
+        toml::Value::Array(array) => {
+            toml::Value::Array(array.into_iter().map(datetimes_to_strings).collect())
+        }
+        toml::Value::Table(table) => toml::Value::Table(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, datetimes_to_strings(value)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// Parse the frontmatter, if any, deep-merged over `defaults`.
+///
+/// The defaults are merged in order, so later ones take precedence, and the
+/// frontmatter takes precedence over all of them. Tables are merged key by
+/// key, other values replace each other.
+pub fn parse_frontmatter_with_defaults<'a, T>(
+    source: &'a str,
+    defaults: &[Value],
+) -> Result<(T, &'a str), FrontmatterError>
+where
+    T: Default + DeserializeOwned,
+{
+    // Parse the frontmatter on its own first, for the positions of errors.
+    let (frontmatter, body) = parse_frontmatter::<T>(source)?;
+
+    if defaults.is_empty() {
+        return Ok((frontmatter, body));
+    }
+
+    let (frontmatter, _) = parse_frontmatter::<Value>(source)?;
+    let mut merged = Value::Object(Default::default());
+
+    // Null, e.g. of an empty frontmatter or defaults file, keeps the defaults.
+    let values = defaults.iter().cloned().chain([frontmatter]);
+
+    for value in values.filter(|value| !value.is_null()) {
+        merge(&mut merged, value);
+    }
+
+    let frontmatter = T::deserialize(merged).map_err(|source| FrontmatterError {
+        offset: None,
+        source: source.into(),
+    })?;
+
     Ok((frontmatter, body))
 }
 
+/// Deep-merge `value` into `base`.
+///
+/// Null values within `value` replace those of `base`, e.g. to unset a default.
+fn merge(base: &mut Value, value: Value) {
+    match (base, value) {
+        (Value::Object(base), Value::Object(value)) => {
+            for (key, value) in value {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, value) => *base = value,
+    }
+}
+
+/// The byte offset in the frontmatter of a YAML error, if it has a location.
+fn yaml_error_offset(frontmatter: &str, error: &serde_yaml_ng::Error) -> Option<usize> {
+    let location = error.location()?;
+    let line_start: usize = frontmatter
+        .split_inclusive('\n')
+        .take(location.line().saturating_sub(1))
+        .map(str::len)
+        .sum();
+    let column = frontmatter[line_start..]
+        .char_indices()
+        .nth(location.column().saturating_sub(1))
+        .map_or(frontmatter.len() - line_start, |(column, _)| column);
+
+    Some(line_start + column)
+}
+
 #[derive(Debug, Error)]
 #[error("Error while parsing frontmatter.")]
-pub struct FrontmatterError(#[source] serde_yaml_ng::Error);
+pub struct FrontmatterError {
+    offset: Option<usize>,
+    #[source]
+    source: Box<dyn Error + Send + Sync>,
+}
+
+impl FrontmatterError {
+    /// The byte offset of the error in the source of the document, if known.
+    pub fn offset(&self) -> Option<usize> {
+        self.offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq, Deserialize)]
+    #[serde(default)]
+    struct Header {
+        title: String,
+        date: Option<String>,
+    }
+
+    fn split(source: &str) -> (Option<(FrontmatterFormat, &str)>, &str) {
+        let split = split_frontmatter(source);
+        assert_eq!(&source[split.body_offset..], split.body);
+
+        if let Some(frontmatter) = split.frontmatter {
+            let end = frontmatter.offset + frontmatter.content.len();
+            assert_eq!(&source[frontmatter.offset..end], frontmatter.content);
+        }
+
+        let frontmatter = split
+            .frontmatter
+            .map(|frontmatter| (frontmatter.format, frontmatter.content));
+        (frontmatter, split.body)
+    }
+
+    #[test]
+    fn split_yaml_and_toml() {
+        assert_eq!(
+            split("---\ntitle: A\n---\nbody\n"),
+            (Some((FrontmatterFormat::Yaml, "title: A\n")), "body\n")
+        );
+        assert_eq!(
+            split("+++\ntitle = \"A\"\n+++\nbody\n"),
+            (Some((FrontmatterFormat::Toml, "title = \"A\"\n")), "body\n")
+        );
+        assert_eq!(split("body\n---\n"), (None, "body\n---\n"));
+    }
+
+    #[test]
+    fn split_body_offset() {
+        let split = split_frontmatter("---\ntitle: A\n---\nbody\n");
+        assert_eq!(split.frontmatter.unwrap().offset, 4);
+        assert_eq!(split.body_offset, 17);
+    }
+
+    #[test]
+    fn split_bom_crlf_and_trailing_whitespace() {
+        assert_eq!(
+            split("\u{feff}---\ntitle: A\n---\nbody"),
+            (Some((FrontmatterFormat::Yaml, "title: A\n")), "body")
+        );
+        assert_eq!(split("\u{feff}body").1, "body");
+        assert_eq!(
+            split("---\r\ntitle: A\r\n---\r\nbody\r\n"),
+            (Some((FrontmatterFormat::Yaml, "title: A\r\n")), "body\r\n")
+        );
+        assert_eq!(
+            split("+++ \t\ntitle = \"A\"\n+++  \nbody"),
+            (Some((FrontmatterFormat::Toml, "title = \"A\"\n")), "body")
+        );
+    }
+
+    #[test]
+    fn split_closing_fence() {
+        assert_eq!(
+            split("---\ntitle: A\n---"),
+            (Some((FrontmatterFormat::Yaml, "title: A\n")), "")
+        );
+        // A fence of the other format does not close the block.
+        assert_eq!(
+            split("---\ntitle: A\n+++\nbody"),
+            (None, "---\ntitle: A\n+++\nbody")
+        );
+        assert_eq!(split("---\ntitle: A\n"), (None, "---\ntitle: A\n"));
+    }
+
+    #[test]
+    fn parse_yaml_and_toml() {
+        let yaml = "---\ntitle: A\ndate: 2024-01-01\n---\nbody\n";
+        let toml = "+++\ntitle = \"A\"\ndate = 2024-01-01\n+++\nbody\n";
+        let header = || Header {
+            title: "A".into(),
+            date: Some("2024-01-01".into()),
+        };
+
+        assert_eq!(parse_frontmatter(yaml).unwrap(), (header(), "body\n"));
+        assert_eq!(parse_frontmatter(toml).unwrap(), (header(), "body\n"));
+        assert_eq!(
+            parse_frontmatter("body\n").unwrap(),
+            (Header::default(), "body\n")
+        );
+    }
+
+    #[test]
+    fn parse_toml_datetimes() {
+        let source = "+++\nupdated = 2024-01-01T10:00:00Z\ntimes = [10:30:00]\n+++\n";
+        let (value, _) = parse_frontmatter::<Value>(source).unwrap();
+
+        assert_eq!(
+            value,
+            json!({"updated": "2024-01-01T10:00:00Z", "times": ["10:30:00"]})
+        );
+    }
+
+    #[test]
+    fn merge_values() {
+        let mut base = json!({"title": "A", "math": {"macros": {"R": "x"}, "fleqn": true}});
+        merge(
+            &mut base,
+            json!({"title": "B", "math": {"macros": {"Q": "y"}, "fleqn": null}, "tags": []}),
+        );
+
+        assert_eq!(
+            base,
+            json!({
+                "title": "B",
+                "math": {"macros": {"R": "x", "Q": "y"}, "fleqn": null},
+                "tags": [],
+            })
+        );
+    }
+
+    #[test]
+    fn merge_non_tables() {
+        let mut base = json!({"tags": ["a"]});
+        merge(&mut base, json!({"tags": ["b"]}));
+        assert_eq!(base, json!({"tags": ["b"]}));
+
+        let mut base = json!({"math": {"fleqn": true}});
+        merge(&mut base, json!({"math": "none"}));
+        assert_eq!(base, json!({"math": "none"}));
+    }
+
+    #[test]
+    fn parse_with_defaults() {
+        let defaults = [
+            json!({"title": "Default", "date": "2024-01-01"}),
+            Value::Null,
+        ];
+
+        let (header, body) =
+            parse_frontmatter_with_defaults::<Header>("---\n---\nbody\n", &defaults).unwrap();
+        assert_eq!(header.title, "Default");
+        assert_eq!(header.date.as_deref(), Some("2024-01-01"));
+        assert_eq!(body, "body\n");
+
+        let source = "---\ntitle: A\ndate: null\n---\n";
+        let (header, _) = parse_frontmatter_with_defaults::<Header>(source, &defaults).unwrap();
+        assert_eq!(header.title, "A");
+        assert_eq!(header.date, None);
+    }
+
+    #[test]
+    fn parse_error_offsets() {
+        let yaml = "---\ntitle: A\n- b\n---\n";
+        let error = parse_frontmatter::<Value>(yaml).unwrap_err();
+        assert_eq!(error.offset(), Some(13));
+
+        let toml = "+++\ntitle = \"A\"\ntitle = \"B\"\n+++\n";
+        let error = parse_frontmatter::<Value>(toml).unwrap_err();
+        assert_eq!(error.offset(), Some(16));
+    }
+}
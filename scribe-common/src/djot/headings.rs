@@ -1,4 +1,10 @@
+use std::ops::Range;
+
 use jotdown::{Container, Event};
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::filter::{Events, Filter};
 
 /// Demote the headings in the document by a fixed offset.
 pub struct DemoteHeadings<I> {
@@ -32,12 +38,14 @@ impl<I> DemoteHeadings<I> {
 
 impl<'a, I> Iterator for DemoteHeadings<I>
 where
-    I: Iterator<Item = Event<'a>>,
+    I: Iterator<Item = (Event<'a>, Range<usize>)>,
 {
-    type Item = Event<'a>;
+    type Item = (Event<'a>, Range<usize>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        Some(match self.inner.next()? {
+        let (event, range) = self.inner.next()?;
+
+        let event = match event {
             Event::Start(container, attributes) => {
                 let container = self.map_container(container);
                 Event::Start(container, attributes)
@@ -47,6 +55,41 @@ where
                 Event::End(container)
             }
             event => event,
-        })
+        };
+
+        Some((event, range))
+    }
+}
+
+/// [`DemoteHeadings`] as a [`Filter`], with the offset as its option, e.g.
+/// `{ offset = 2 }`.
+#[derive(Debug, Clone, Copy)]
+pub struct DemoteHeadingsFilter {
+    offset: u16,
+}
+
+impl DemoteHeadingsFilter {
+    /// Create a filter that demotes headings by `offset` unless configured
+    /// otherwise.
+    pub fn new(offset: u16) -> Self {
+        Self { offset }
+    }
+}
+
+impl<'a> Filter<'a> for DemoteHeadingsFilter {
+    fn configure(&mut self, options: Value) -> Result<(), serde_json::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Options {
+            offset: Option<u16>,
+        }
+
+        let options: Options = serde_json::from_value(options)?;
+        self.offset = options.offset.unwrap_or(self.offset);
+        Ok(())
+    }
+
+    fn apply(self: Box<Self>, events: Events<'a>) -> Events<'a> {
+        Box::new(DemoteHeadings::new(events, self.offset))
     }
 }
@@ -0,0 +1,27 @@
+use jotdown::Attributes;
+
+/// Escape text for use in HTML content and attribute values.
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Render attributes the way the jotdown renderer does, with `classes` first.
+pub(crate) fn attributes_html(classes: &[&str], attributes: &Attributes) -> String {
+    let mut classes: Vec<String> = classes.iter().map(|class| class.to_string()).collect();
+    let mut output = String::new();
+
+    for (key, value) in attributes.unique_pairs() {
+        match key {
+            "class" => classes.push(value.to_string()),
+            _ => output.push_str(&format!(" {}=\"{}\"", key, escape_html(&value.to_string()))),
+        }
+    }
+
+    match classes.is_empty() {
+        true => output,
+        false => format!(" class=\"{}\"{}", escape_html(&classes.join(" ")), output),
+    }
+}
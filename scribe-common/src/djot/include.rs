@@ -0,0 +1,420 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use jotdown::{AttributeKind, AttributeValue, Attributes, Container, Event};
+use thiserror::Error;
+use tracing::trace;
+
+use super::error::Located;
+
+/// Include code from files into code blocks.
+///
+/// A code block with an `include` attribute gets the contents of the file at
+/// this path, relative to the base directory, e.g. the directory of the note.
+/// Parts of the file are selected with one of these attributes:
+///
+/// - `lines="10-40"` selects a range of lines. Either end can be left out,
+///   e.g. `lines="10-"`, and a single number selects a single line.
+/// - `region=parse` selects the lines between a line with `#region parse`
+///   and the matching line with `#endregion`, typically in comments. Marker
+///   lines of other regions in between are left out.
+///
+/// Selected parts are dedented. When the code block has line numbers, they
+/// start at the first selected line unless the code block sets `start` itself,
+/// and continue without gaps where region markers are left out. The code keeps
+/// the language of the code block, so that it is highlighted as usual.
+#[derive(Debug, Clone)]
+pub struct IncludeCode<'a, I> {
+    inner: I,
+    base: PathBuf,
+    included: IncludedFiles,
+    buffer: Vec<Event<'a>>,
+    /// The range of the element that the buffered events replace.
+    range: Range<usize>,
+}
+
+impl<'a, I> IncludeCode<'a, I> {
+    pub fn new(inner: I, base: impl Into<PathBuf>) -> Self {
+        Self {
+            inner,
+            base: base.into(),
+            included: IncludedFiles::new(),
+            buffer: Vec::with_capacity(2),
+            range: 0..0,
+        }
+    }
+
+    /// Record the included files in a shared record.
+    pub fn with_included(mut self, included: IncludedFiles) -> Self {
+        self.included = included;
+        self
+    }
+
+    /// The code selected by the attributes from the file at `path`.
+    fn include(&self, path: &str, attributes: &mut Attributes<'a>) -> Result<String, IncludeError> {
+        let path = self.base.join(path);
+        trace!("including code from {}", path.display());
+
+        self.included.record(&path);
+
+        let source = std::fs::read_to_string(&path).map_err(|source| IncludeError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        let lines: Vec<&str> = source.lines().collect();
+
+        let selection = match (
+            attributes.get_value("lines"),
+            attributes.get_value("region"),
+        ) {
+            (Some(_), Some(_)) => return Err(IncludeError::Conflict(path)),
+            (Some(range), None) => Some(select_lines(&lines, &range.to_string(), &path)?),
+            (None, Some(name)) => Some(select_region(&lines, &name.to_string(), &path)?),
+            (None, None) => None,
+        };
+
+        let Some((first, selected)) = selection else {
+            return Ok(source);
+        };
+
+        let line_numbers = attributes
+            .get_value("linenos")
+            .is_some_and(|value| value.to_string() == "true");
+
+        if line_numbers && !attributes.contains_key("start") {
+            attributes.push((
+                AttributeKind::Pair { key: "start" },
+                AttributeValue::from((first + 1).to_string()),
+            ));
+        }
+
+        Ok(dedent(&selected))
+    }
+}
+
+impl<'a, I> Iterator for IncludeCode<'a, I>
+where
+    I: Iterator<Item = (Event<'a>, Range<usize>)>,
+{
+    type Item = Result<(Event<'a>, Range<usize>), Located<IncludeError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.buffer.pop() {
+            return Some(Ok((event, self.range.clone())));
+        }
+
+        let (language, mut attributes) = match self.inner.next()? {
+            (Event::Start(Container::CodeBlock { language }, attributes), range)
+                if attributes.contains_key("include") =>
+            {
+                self.range = range;
+                (language, attributes)
+            }
+            event => return Some(Ok(event)),
+        };
+
+        // The included code replaces the contents of the code block.
+        for (event, range) in self.inner.by_ref() {
+            if matches!(event, Event::End(Container::CodeBlock { .. })) {
+                self.range.end = range.end;
+                break;
+            }
+        }
+
+        let path = attributes.get_value("include").unwrap().to_string();
+        let result = self.include(&path, &mut attributes);
+
+        attributes.retain(|(kind, _)| {
+            !matches!(
+                kind,
+                AttributeKind::Pair {
+                    key: "include" | "lines" | "region"
+                }
+            )
+        });
+
+        let code = match result {
+            Ok(code) => code,
+            Err(err) => return Some(Err(Located::new(err, self.range.clone()))),
+        };
+
+        self.buffer.extend([
+            Event::End(Container::CodeBlock { language }),
+            Event::Str(code.into()),
+        ]);
+
+        Some(Ok((
+            Event::Start(Container::CodeBlock { language }, attributes),
+            self.range.clone(),
+        )))
+    }
+}
+
+/// Select a range of lines like `10-40`, returning the index of the first.
+fn select_lines(lines: &[&str], range: &str, path: &Path) -> Result<(usize, String), IncludeError> {
+    let invalid = || IncludeError::Lines {
+        range: range.to_string(),
+        path: path.to_path_buf(),
+        count: lines.len(),
+    };
+
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (start.trim(), end.trim()),
+        None => (range.trim(), range.trim()),
+    };
+
+    let start: usize = match start {
+        "" => 1,
+        start => start.parse().map_err(|_| invalid())?,
+    };
+
+    let end: usize = match end {
+        "" => lines.len(),
+        end => end.parse().map_err(|_| invalid())?,
+    };
+
+    if start == 0 || start > end || end > lines.len() {
+        return Err(invalid());
+    }
+
+    Ok((start - 1, join_lines(&lines[start - 1..end])))
+}
+
+/// Select the lines of a region, returning the index of the first that is kept.
+fn select_region(lines: &[&str], name: &str, path: &Path) -> Result<(usize, String), IncludeError> {
+    let missing = || IncludeError::Region {
+        name: name.to_string(),
+        path: path.to_path_buf(),
+    };
+
+    let start = lines
+        .iter()
+        .position(|line| region_marker(line, "#region") == Some(name))
+        .ok_or_else(missing)?;
+
+    let mut selected = Vec::new();
+    let mut first = None;
+    let mut depth = 0;
+
+    for (index, line) in lines.iter().enumerate().skip(start + 1) {
+        if region_marker(line, "#region").is_some() {
+            depth += 1;
+            continue;
+        }
+
+        if region_marker(line, "#endregion").is_some() {
+            if depth == 0 {
+                return Ok((first.unwrap_or(start + 1), join_lines(&selected)));
+            }
+
+            depth -= 1;
+            continue;
+        }
+
+        first.get_or_insert(index);
+        selected.push(*line);
+    }
+
+    Err(missing())
+}
+
+/// The name after a region marker on a line, which is empty if there is none.
+fn region_marker<'s>(line: &'s str, marker: &str) -> Option<&'s str> {
+    let (_, rest) = line.split_once(marker)?;
+
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    Some(rest.split_whitespace().next().unwrap_or(""))
+}
+
+fn join_lines(lines: &[&str]) -> String {
+    let mut code = lines.join("\n");
+    code.push('\n');
+    code
+}
+
+/// Remove the indentation that all non-blank lines have in common.
+fn dedent(code: &str) -> String {
+    let indent = code
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+
+    code.lines()
+        .map(|line| line.get(indent..).unwrap_or("").trim_end())
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+/// Files included by [`IncludeCode`].
+///
+/// The record can be cloned to share it between documents, e.g. to watch the
+/// included files of all notes for changes.
+#[derive(Debug, Clone, Default)]
+pub struct IncludedFiles(Arc<Mutex<BTreeSet<PathBuf>>>);
+
+impl IncludedFiles {
+    /// Create an empty record.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn record(&self, path: &Path) {
+        let mut files = self.0.lock().unwrap();
+        files.insert(path.to_path_buf());
+    }
+
+    /// The paths of the included files.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let files = self.0.lock().unwrap();
+        files.iter().cloned().collect()
+    }
+}
+
+/// Error produced by [`IncludeCode`].
+#[derive(Debug, Error)]
+pub enum IncludeError {
+    /// Error while reading an included file.
+    #[error("failed to include {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// Invalid range of lines, or range beyond the end of the file.
+    #[error("invalid lines `{range}` of {}, which has {count} lines", path.display())]
+    Lines {
+        range: String,
+        path: PathBuf,
+        count: usize,
+    },
+    /// Region that is missing or not closed.
+    #[error("no region `{name}` in {}", path.display())]
+    Region { name: String, path: PathBuf },
+    /// Both `lines` and `region` are given.
+    #[error("cannot select both lines and a region of {}", .0.display())]
+    Conflict(PathBuf),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+fn main() {
+    // #region setup
+    // #region inner
+    let x = 1;
+    // #endregion
+    let y = 2;
+    // #endregion
+    println!(\"{}\", x + y);
+}
+";
+
+    fn lines() -> Vec<&'static str> {
+        SOURCE.lines().collect()
+    }
+
+    #[test]
+    fn select_line_ranges() {
+        let lines = lines();
+        let path = Path::new("main.rs");
+
+        assert_eq!(
+            select_lines(&lines, "1-2", path).unwrap(),
+            (0, "fn main() {\n    // #region setup\n".into())
+        );
+        assert_eq!(select_lines(&lines, "9", path).unwrap(), (8, "}\n".into()));
+        assert_eq!(select_lines(&lines, "8-", path).unwrap().0, 7);
+        assert_eq!(select_lines(&lines, "-1", path).unwrap().0, 0);
+    }
+
+    #[test]
+    fn reject_invalid_line_ranges() {
+        let lines = lines();
+        let path = Path::new("main.rs");
+
+        for range in ["0-2", "3-2", "1-10", "x", "1-y"] {
+            assert!(matches!(
+                select_lines(&lines, range, path),
+                Err(IncludeError::Lines { count: 9, .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn select_region_without_nested_markers() {
+        let lines = lines();
+        let path = Path::new("main.rs");
+
+        assert_eq!(
+            select_region(&lines, "setup", path).unwrap(),
+            (3, "    let x = 1;\n    let y = 2;\n".into())
+        );
+        assert_eq!(
+            select_region(&lines, "inner", path).unwrap(),
+            (3, "    let x = 1;\n".into())
+        );
+        assert!(matches!(
+            select_region(&lines, "missing", path),
+            Err(IncludeError::Region { .. })
+        ));
+        assert!(select_region(&["// #region open", "x"], "open", path).is_err());
+    }
+
+    #[test]
+    fn region_markers_need_a_separator() {
+        assert_eq!(region_marker("// #region setup", "#region"), Some("setup"));
+        assert_eq!(region_marker("// #endregion", "#endregion"), Some(""));
+        assert_eq!(region_marker("// #regions", "#region"), None);
+    }
+
+    #[test]
+    fn dedent_common_indentation() {
+        assert_eq!(dedent("    a\n\n      b  \n"), "a\n\n  b\n");
+    }
+
+    fn include(attributes: &str) -> Vec<String> {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.rs"), SOURCE).unwrap();
+
+        let source = format!("{{include=\"main.rs\" {}}}\n```rust\n```\n", attributes);
+        let events = jotdown::Parser::new(&source).into_offset_iter();
+
+        IncludeCode::new(events, dir.path())
+            .map(|result| result.unwrap().0)
+            .find_map(|event| match event {
+                Event::Start(Container::CodeBlock { .. }, attributes) => Some(
+                    attributes
+                        .unique_pairs()
+                        .map(|(key, value)| format!("{}={}", key, value))
+                        .collect(),
+                ),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn start_only_with_line_numbers() {
+        assert!(include("lines=\"2-4\"").is_empty());
+        assert_eq!(
+            include("lines=\"2-4\" linenos=true"),
+            ["linenos=true", "start=2"]
+        );
+        assert_eq!(
+            include("region=setup linenos=true"),
+            ["linenos=true", "start=4"]
+        );
+        assert_eq!(
+            include("lines=\"2-4\" linenos=true start=1"),
+            ["linenos=true", "start=1"]
+        );
+    }
+}
@@ -1,16 +1,51 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Range, RangeInclusive};
+use std::sync::{Arc, Mutex};
+
 use inkjet::{Highlighter, Language};
-use jotdown::{Container, Event};
+use jotdown::{AttributeKind, Attributes, Container, Event};
 use thiserror::Error;
-use tracing::trace;
+use tracing::{trace, warn};
+
+use super::error::Located;
+use super::html::{attributes_html, escape_html};
+
+/// Formats of raw content that is passed through to the output in that format
+/// rather than highlighted, even if they name a language.
+const OUTPUT_FORMATS: &[&str] = &["html", "latex", "tex", "markdown", "ms", "man"];
 
-/// Render code blocks to HTML using Inkjet.
+/// Render code blocks and inline code to HTML using Inkjet.
+///
+/// Languages are looked up in the aliases first, then by Inkjet's own names.
+/// Code blocks for languages that are not supported by Inkjet are highlighted
+/// as the fallback language if there is one, and are otherwise left unmodified,
+/// unless they use one of the attributes below. Either way, they are recorded
+/// in [`UnsupportedLanguages`]. Highlighted blocks get the
+/// `highlight` class, see [`theme_css`](super::theme_css) for a matching
+/// stylesheet, and their code keeps the language as a `language-*` class.
+///
+/// The following attributes control the rendering of a code block:
 ///
-/// Code blocks for languages that are not supported by Inkjet are left unmodified.
+/// - `linenos=true` numbers the lines, starting at `start=n` (1 by default).
+///   The numbers are set as `data-line` attributes of the lines.
+/// - `hl_lines="3-5 8"` highlights lines with the `hl` class. Lines are
+///   counted from the first line of the block, regardless of `start`.
+/// - `caption="..."` wraps the block in a `<figure>` with the caption.
+///
+/// Inline verbatim is highlighted if it has a class naming a language, as in
+/// `` `let x`{.rust} ``, and so is raw inline content in a language, as in
+/// `` `Vec<T>`{=rust} ``. Raw inline content in an output format, like `html`
+/// or `latex`, or in a format that names no language is left unmodified.
 #[derive(Clone)]
 pub struct InkjetCode<'a, I> {
     inner: I,
     highlighter: Highlighter,
+    aliases: HashMap<String, Language>,
+    fallback: Option<Language>,
+    unsupported: UnsupportedLanguages,
     buffer: Vec<Event<'a>>,
+    /// The range of the element that the buffered events replace.
+    range: Range<usize>,
 }
 
 impl<'a, I> InkjetCode<'a, I> {
@@ -18,73 +53,398 @@ impl<'a, I> InkjetCode<'a, I> {
         Self {
             inner,
             highlighter,
+            aliases: HashMap::new(),
+            fallback: None,
+            unsupported: UnsupportedLanguages::new(),
             buffer: Vec::with_capacity(2),
+            range: 0..0,
         }
     }
-}
 
-impl<'a, I> Iterator for InkjetCode<'a, I>
-where
-    I: Iterator<Item = Event<'a>>,
-{
-    type Item = Result<Event<'a>, InkjetCodeError>;
+    /// Highlight code blocks labelled with an alias as the given language.
+    pub fn with_aliases(mut self, aliases: impl IntoIterator<Item = (String, Language)>) -> Self {
+        self.aliases.extend(aliases);
+        self
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Some(event) = self.buffer.pop() {
-            return Some(Ok(event));
+    /// Highlight code blocks in unsupported languages as `language`.
+    pub fn with_fallback(mut self, language: Option<Language>) -> Self {
+        self.fallback = language;
+        self
+    }
+
+    /// Record unsupported languages in a shared record.
+    pub fn with_unsupported(mut self, unsupported: UnsupportedLanguages) -> Self {
+        self.unsupported = unsupported;
+        self
+    }
+
+    /// The language named by `token`, either as an alias or by Inkjet.
+    fn lookup(&self, token: &str) -> Option<Language> {
+        self.aliases
+            .get(token)
+            .copied()
+            .or_else(|| Language::from_token(token))
+    }
+
+    /// The language to highlight a code block labelled `token` as.
+    fn language(&self, token: &str) -> Option<Language> {
+        if token.is_empty() {
+            return None;
         }
 
-        let (language, attributes) = match self.inner.next()? {
-            Event::Start(Container::CodeBlock { language }, attributes) => (language, attributes),
-            event => return Some(Ok(event)),
-        };
+        let language = self.lookup(token);
 
-        trace!("code block with language `{}`", language);
+        if language.is_none() {
+            warn!("code block in unsupported language `{}`", token);
+            self.unsupported.record(token);
+        }
+
+        language.or(self.fallback)
+    }
+
+    fn highlight(&mut self, language: Language, code: &str) -> Result<String, InkjetCodeError> {
+        let html =
+            self.highlighter
+                .highlight_to_string(language, &inkjet::formatter::Html, code)?;
+        Ok(html)
+    }
+}
 
+impl<'a, I> InkjetCode<'a, I>
+where
+    I: Iterator<Item = (Event<'a>, Range<usize>)>,
+{
+    /// Collect the code of a verbatim element, up to and including its end.
+    fn collect_code(&mut self) -> Result<String, InkjetCodeError> {
         let mut code = String::new();
 
-        loop {
-            match self.inner.next()? {
+        for (event, range) in self.inner.by_ref() {
+            self.range.end = range.end;
+
+            match event {
                 Event::End(_) => break,
                 Event::Str(str) => code.push_str(&str),
-                _ => return Some(Err(InkjetCodeError::Unexpected)),
+                _ => return Err(InkjetCodeError::Unexpected),
             }
         }
 
-        let Some(language) = Language::from_token(language) else {
-            trace!("language `{}` not supported by Inkjet", language);
+        Ok(code)
+    }
+
+    fn code_block(
+        &mut self,
+        language: &'a str,
+        mut attributes: Attributes<'a>,
+    ) -> Result<Event<'a>, InkjetCodeError> {
+        trace!("code block with language `{}`", language);
+
+        let code = self.collect_code()?;
+        let options = CodeOptions::take(&mut attributes)?;
+
+        let html = match self.language(language) {
+            Some(inkjet_language) => self.highlight(inkjet_language, &code)?,
+            None if options.is_default() => {
+                self.buffer.extend([
+                    Event::End(Container::CodeBlock { language }),
+                    Event::Str(code.into()),
+                ]);
+
+                return Ok(Event::Start(Container::CodeBlock { language }, attributes));
+            }
+            None => escape_html(&code),
+        };
+
+        let result = options.render(language, &attributes, &html);
+        Ok(self.raw_html(Container::RawBlock { format: "html" }, result))
+    }
+
+    /// Inline verbatim with a class naming a language, e.g. `` `let x`{.rust} ``.
+    fn verbatim(&mut self, mut attributes: Attributes<'a>) -> Result<Event<'a>, InkjetCodeError> {
+        let code = self.collect_code()?;
+
+        let language = attributes.iter().find_map(|(kind, value)| match kind {
+            AttributeKind::Class => {
+                let token = value.to_string();
+                Some((self.lookup(&token)?, token))
+            }
+            _ => None,
+        });
+
+        let Some((language, token)) = language else {
+            self.buffer
+                .extend([Event::End(Container::Verbatim), Event::Str(code.into())]);
+
+            return Ok(Event::Start(Container::Verbatim, attributes));
+        };
+
+        attributes
+            .retain(|(kind, value)| !(*kind == AttributeKind::Class && value.to_string() == token));
+
+        let html = self.highlight(language, &code)?;
+        Ok(self.inline_code(&token, &attributes, &html))
+    }
+
+    /// Raw inline content in a language, e.g. `` `Vec<T>`{=rust} ``.
+    fn raw_inline(
+        &mut self,
+        format: &'a str,
+        attributes: Attributes<'a>,
+    ) -> Result<Event<'a>, InkjetCodeError> {
+        let code = self.collect_code()?;
 
+        let Some(language) = self.lookup(format) else {
             self.buffer.extend([
-                Event::End(Container::CodeBlock { language }),
+                Event::End(Container::RawInline { format }),
                 Event::Str(code.into()),
             ]);
 
-            return Some(Ok(Event::Start(
-                Container::CodeBlock { language },
-                attributes,
-            )));
+            return Ok(Event::Start(Container::RawInline { format }, attributes));
         };
 
-        let result = self
-            .highlighter
-            .highlight_to_string(language, &inkjet::formatter::Html, code);
+        let html = self.highlight(language, &code)?;
+        Ok(self.inline_code(format, &attributes, &html))
+    }
+
+    /// Queue highlighted inline code and return the start of its raw element.
+    fn inline_code(&mut self, token: &str, attributes: &Attributes, html: &str) -> Event<'a> {
+        let class = format!("language-{}", token);
+        let html = format!(
+            "<code{}>{}</code>",
+            attributes_html(&["highlight", &class], attributes),
+            html
+        );
+
+        self.raw_html(Container::RawInline { format: "html" }, html)
+    }
+
+    /// Queue raw HTML and return the start of its element.
+    fn raw_html(&mut self, container: Container<'a>, html: String) -> Event<'a> {
+        self.buffer
+            .extend([Event::End(container.clone()), Event::Str(html.into())]);
+
+        Event::Start(container, Attributes::new())
+    }
+}
+
+impl<'a, I> Iterator for InkjetCode<'a, I>
+where
+    I: Iterator<Item = (Event<'a>, Range<usize>)>,
+{
+    type Item = Result<(Event<'a>, Range<usize>), Located<InkjetCodeError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.buffer.pop() {
+            return Some(Ok((event, self.range.clone())));
+        }
+
+        let (event, range) = self.inner.next()?;
+        self.range = range;
 
-        let result = match result {
-            Ok(result) => result,
-            Err(err) => return Some(Err(err.into())),
+        let result = match event {
+            Event::Start(Container::CodeBlock { language }, attributes) => {
+                self.code_block(language, attributes)
+            }
+            Event::Start(Container::Verbatim, attributes) => self.verbatim(attributes),
+            Event::Start(Container::RawInline { format }, attributes)
+                if !OUTPUT_FORMATS.contains(&format) =>
+            {
+                self.raw_inline(format, attributes)
+            }
+            event => Ok(event),
         };
 
-        let result = format!("<pre><code>{}</code></pre>", result);
+        Some(match result {
+            Ok(event) => Ok((event, self.range.clone())),
+            Err(error) => Err(Located::new(error, self.range.clone())),
+        })
+    }
+}
+
+/// Rendering options of a code block, set by its attributes.
+#[derive(Debug, Clone, Default)]
+struct CodeOptions {
+    line_numbers: bool,
+    start: Option<usize>,
+    highlighted: Vec<RangeInclusive<usize>>,
+    caption: Option<String>,
+}
 
-        self.buffer.extend([
-            Event::End(Container::RawBlock { format: "html" }),
-            Event::Str(result.into()),
-        ]);
+impl CodeOptions {
+    /// Remove the options from the attributes of a code block.
+    fn take(attributes: &mut Attributes) -> Result<Self, InkjetCodeError> {
+        let mut options = Self::default();
+
+        for (kind, value) in attributes.iter() {
+            let AttributeKind::Pair { key } = kind else {
+                continue;
+            };
+
+            let value = value.to_string();
+            let invalid = || InkjetCodeError::Attribute {
+                key: key.to_string(),
+                value: value.clone(),
+            };
+
+            match *key {
+                "linenos" => {
+                    options.line_numbers = match value.as_str() {
+                        "true" => true,
+                        "false" => false,
+                        _ => return Err(invalid()),
+                    };
+                }
+                "start" => options.start = Some(value.parse().map_err(|_| invalid())?),
+                "hl_lines" => options.highlighted = parse_lines(&value).ok_or_else(invalid)?,
+                "caption" => options.caption = Some(value),
+                _ => {}
+            }
+        }
+
+        attributes.retain(|(kind, _)| {
+            !matches!(
+                kind,
+                AttributeKind::Pair {
+                    key: "linenos" | "start" | "hl_lines" | "caption"
+                }
+            )
+        });
 
-        Some(Ok(Event::Start(
-            Container::RawBlock { format: "html" },
-            attributes,
-        )))
+        Ok(options)
+    }
+
+    fn is_default(&self) -> bool {
+        !self.line_numbers
+            && self.start.is_none()
+            && self.highlighted.is_empty()
+            && self.caption.is_none()
+    }
+
+    /// Render a code block around the highlighted `html`.
+    fn render(&self, language: &str, attributes: &Attributes, html: &str) -> String {
+        let html = match self.line_numbers || !self.highlighted.is_empty() {
+            true => self.wrap_lines(html),
+            false => html.to_string(),
+        };
+
+        let code_class = match language.is_empty() {
+            true => String::new(),
+            false => format!(" class=\"language-{}\"", escape_html(language)),
+        };
+
+        let pre = format!(
+            "<pre{}><code{}>{}</code></pre>",
+            attributes_html(&["highlight"], attributes),
+            code_class,
+            html
+        );
+
+        match &self.caption {
+            Some(caption) => format!(
+                "<figure>\n{}\n<figcaption>{}</figcaption>\n</figure>",
+                pre,
+                escape_html(caption)
+            ),
+            None => pre,
+        }
+    }
+
+    /// Wrap each line in a `code-line` span.
+    ///
+    /// Spans of the highlighter that cross lines are closed at the end of a
+    /// line and reopened on the next one, so that the lines are well nested.
+    fn wrap_lines(&self, html: &str) -> String {
+        let mut output = String::with_capacity(html.len() * 2);
+        let mut open: Vec<&str> = Vec::new();
+        let html = html.strip_suffix('\n').unwrap_or(html);
+        let start = self.start.unwrap_or(1);
+
+        for (index, line) in html.split('\n').enumerate() {
+            let number = start + index;
+
+            output.push_str("<span class=\"code-line");
+
+            if self
+                .highlighted
+                .iter()
+                .any(|range| range.contains(&(index + 1)))
+            {
+                output.push_str(" hl");
+            }
+
+            output.push('"');
+
+            if self.line_numbers {
+                output.push_str(&format!(" data-line=\"{}\"", number));
+            }
+
+            output.push('>');
+            output.extend(open.iter().copied());
+            output.push_str(line);
+
+            let mut rest = line;
+
+            while let Some(start) = rest.find('<') {
+                let end = rest[start..]
+                    .find('>')
+                    .map_or(rest.len(), |end| start + end + 1);
+
+                match rest[start..].starts_with("</") {
+                    true => drop(open.pop()),
+                    false => open.push(&rest[start..end]),
+                }
+
+                rest = &rest[end..];
+            }
+
+            output.extend(open.iter().map(|_| "</span>"));
+            output.push_str("\n</span>");
+        }
+
+        output
+    }
+}
+
+/// Parse line numbers and ranges, e.g. `1 3-5,8`.
+fn parse_lines(value: &str) -> Option<Vec<RangeInclusive<usize>>> {
+    value
+        .split([',', ' '])
+        .filter(|part| !part.is_empty())
+        .map(|part| match part.split_once('-') {
+            Some((start, end)) => Some(start.trim().parse().ok()?..=end.trim().parse().ok()?),
+            None => {
+                let line = part.trim().parse().ok()?;
+                Some(line..=line)
+            }
+        })
+        .collect()
+}
+
+/// Languages of code blocks that [`InkjetCode`] does not support.
+///
+/// The record can be cloned to share it between documents, e.g. to report the
+/// unsupported languages of all notes after a build.
+#[derive(Debug, Clone, Default)]
+pub struct UnsupportedLanguages(Arc<Mutex<BTreeMap<String, usize>>>);
+
+impl UnsupportedLanguages {
+    /// Create an empty record.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn record(&self, language: &str) {
+        let mut languages = self.0.lock().unwrap();
+        *languages.entry(language.to_string()).or_default() += 1;
+    }
+
+    /// The unsupported languages and how many code blocks use them.
+    pub fn languages(&self) -> Vec<(String, usize)> {
+        let languages = self.0.lock().unwrap();
+        languages
+            .iter()
+            .map(|(language, count)| (language.clone(), *count))
+            .collect()
     }
 }
 
@@ -97,4 +457,137 @@ pub enum InkjetCodeError {
     /// Unexpected [`Event`] in code block.
     #[error("unexpected event in code block")]
     Unexpected,
+    /// Invalid value of a code block attribute.
+    #[error("invalid value `{value}` for code block attribute `{key}`")]
+    Attribute { key: String, value: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attributes(pairs: &[(&'static str, &'static str)]) -> Attributes<'static> {
+        let mut attributes = Attributes::new();
+
+        for (key, value) in pairs {
+            attributes.push((AttributeKind::Pair { key }, (*value).into()));
+        }
+
+        attributes
+    }
+
+    fn render(source: &str) -> String {
+        let events = jotdown::Parser::new(source).into_offset_iter();
+        let events = InkjetCode::new(events, Highlighter::new()).map(|result| result.unwrap().0);
+        jotdown::html::render_to_string(events)
+    }
+
+    #[test]
+    fn verbatim_with_language_is_highlighted() {
+        let html = render("`let x`{.rust .big}\n");
+
+        assert!(html.contains("<code class=\"highlight language-rust big\">"));
+    }
+
+    #[test]
+    fn verbatim_without_language_is_unmodified() {
+        assert_eq!(
+            render("`x`{.big}\n"),
+            "<p><code class=\"big\">x</code></p>\n"
+        );
+    }
+
+    #[test]
+    fn raw_inline_in_language_is_highlighted() {
+        let html = render("`Vec<T>`{=rust}\n");
+
+        assert!(html.contains("<code class=\"highlight language-rust\">"));
+        assert!(!html.contains("Vec<T>"));
+    }
+
+    #[test]
+    fn raw_inline_in_other_format_is_unmodified() {
+        // Raw content only shows up in its output format.
+        assert_eq!(render("a`*x*`{=ms}\n"), "<p>a</p>\n");
+        assert_eq!(render("a`\\emph{x}`{=latex}\n"), "<p>a</p>\n");
+        assert_eq!(render("a`<b>x</b>`{=html}\n"), "<p>a<b>x</b></p>\n");
+    }
+
+    #[test]
+    fn parse_lines_and_ranges() {
+        assert_eq!(parse_lines("3"), Some(vec![3..=3]));
+        assert_eq!(parse_lines("1 3-5,8"), Some(vec![1..=1, 3..=5, 8..=8]));
+        assert_eq!(parse_lines("2-4, 6"), Some(vec![2..=4, 6..=6]));
+        assert_eq!(parse_lines(""), Some(vec![]));
+        assert_eq!(parse_lines("1-"), None);
+        assert_eq!(parse_lines("x"), None);
+    }
+
+    #[test]
+    fn take_removes_the_options() {
+        let mut attributes = attributes(&[
+            ("linenos", "true"),
+            ("start", "10"),
+            ("hl_lines", "2-3"),
+            ("caption", "Example"),
+            ("data-x", "y"),
+        ]);
+
+        let options = CodeOptions::take(&mut attributes).unwrap();
+
+        assert!(options.line_numbers);
+        assert_eq!(options.start, Some(10));
+        assert_eq!(options.highlighted, vec![2..=3]);
+        assert_eq!(options.caption.as_deref(), Some("Example"));
+        assert_eq!(attributes.len(), 1);
+        assert!(attributes.contains_key("data-x"));
+    }
+
+    #[test]
+    fn take_rejects_invalid_values() {
+        for (key, value) in [("linenos", "yes"), ("start", "-1"), ("hl_lines", "1-x")] {
+            let result = CodeOptions::take(&mut attributes(&[(key, value)]));
+            assert!(matches!(result, Err(InkjetCodeError::Attribute { .. })));
+        }
+    }
+
+    #[test]
+    fn highlighted_lines_count_from_the_block() {
+        let options = CodeOptions {
+            line_numbers: true,
+            start: Some(10),
+            highlighted: vec![2..=3],
+            ..Default::default()
+        };
+
+        let html = options.wrap_lines("a\nb\nc\nd\n");
+        let lines: Vec<_> = html.split("\n</span>").collect();
+
+        assert_eq!(
+            lines,
+            [
+                "<span class=\"code-line\" data-line=\"10\">a",
+                "<span class=\"code-line hl\" data-line=\"11\">b",
+                "<span class=\"code-line hl\" data-line=\"12\">c",
+                "<span class=\"code-line\" data-line=\"13\">d",
+                "",
+            ]
+        );
+    }
+
+    #[test]
+    fn wrapped_lines_reopen_spans() {
+        let options = CodeOptions {
+            highlighted: vec![2..=2],
+            ..Default::default()
+        };
+
+        let html = options.wrap_lines("<span class=\"s\">\"a\nb\"</span>\n");
+
+        assert_eq!(
+            html,
+            "<span class=\"code-line\"><span class=\"s\">\"a</span>\n</span>\
+             <span class=\"code-line hl\"><span class=\"s\">b\"</span>\n</span>"
+        );
+    }
 }
@@ -0,0 +1,587 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+use jotdown::{
+    Alignment, AttributeKind, AttributeValue, Attributes, Container, Event, LinkType,
+    ListBulletType, ListKind, OrderedListNumbering, OrderedListStyle, SpanLinkType,
+};
+use serde::{Deserialize, Serialize};
+
+use super::strings::Strings;
+
+/// Serialize events with their ranges to a JSON array.
+///
+/// Each event is an object with its `type`, e.g. `start`, `end` or `str`, its
+/// fields and its `range` in the source:
+///
+/// ```json
+/// {"type": "start", "container": {"type": "code_block", "language": "rust"},
+///  "attributes": [{"kind": "class", "value": "example"}],
+///  "range": {"start": 10, "end": 18}}
+/// ```
+///
+/// Names are in snake case, like the names of jotdown's types.
+pub fn events_to_json(events: &[(Event, Range<usize>)]) -> serde_json::Result<String> {
+    let events: Vec<_> = events
+        .iter()
+        .map(|(event, range)| JsonSpanned {
+            event: JsonEvent::from(event),
+            range: Some(range.clone()),
+        })
+        .collect();
+
+    serde_json::to_string(&events)
+}
+
+/// Deserialize events from a JSON array, see [`events_to_json`].
+///
+/// The range of an event is optional, events without one get the range of the
+/// event before them. Strings that jotdown borrows, like classes, are stored in
+/// `strings`.
+pub fn events_from_json<'a>(
+    json: &str,
+    strings: &'a Strings,
+) -> serde_json::Result<Vec<(Event<'a>, Range<usize>)>> {
+    let events: Vec<JsonSpanned> = serde_json::from_str(json)?;
+    let mut last = 0..0;
+
+    let events = events
+        .into_iter()
+        .map(|JsonSpanned { event, range }| {
+            let range = range.unwrap_or_else(|| last.clone());
+            last = range.clone();
+            (event.into_event(strings), range)
+        })
+        .collect();
+
+    Ok(events)
+}
+
+#[derive(Deserialize, Serialize)]
+struct JsonSpanned {
+    #[serde(flatten)]
+    event: JsonEvent,
+    #[serde(default)]
+    range: Option<Range<usize>>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum JsonEvent {
+    Start {
+        container: JsonContainer,
+        #[serde(default)]
+        attributes: Vec<JsonAttribute>,
+    },
+    End {
+        container: JsonContainer,
+    },
+    Str {
+        text: String,
+    },
+    FootnoteReference {
+        label: String,
+    },
+    Symbol {
+        symbol: String,
+    },
+    LeftSingleQuote,
+    RightSingleQuote,
+    LeftDoubleQuote,
+    RightDoubleQuote,
+    Ellipsis,
+    EnDash,
+    EmDash,
+    NonBreakingSpace,
+    Softbreak,
+    Hardbreak,
+    Escape,
+    Blankline,
+    ThematicBreak {
+        #[serde(default)]
+        attributes: Vec<JsonAttribute>,
+    },
+    Attributes {
+        attributes: Vec<JsonAttribute>,
+    },
+}
+
+impl From<&Event<'_>> for JsonEvent {
+    fn from(event: &Event) -> Self {
+        match event {
+            Event::Start(container, attributes) => Self::Start {
+                container: container.into(),
+                attributes: json_attributes(attributes),
+            },
+            Event::End(container) => Self::End {
+                container: container.into(),
+            },
+            Event::Str(text) => Self::Str {
+                text: text.to_string(),
+            },
+            Event::FootnoteReference(label) => Self::FootnoteReference {
+                label: label.to_string(),
+            },
+            Event::Symbol(symbol) => Self::Symbol {
+                symbol: symbol.to_string(),
+            },
+            Event::LeftSingleQuote => Self::LeftSingleQuote,
+            Event::RightSingleQuote => Self::RightSingleQuote,
+            Event::LeftDoubleQuote => Self::LeftDoubleQuote,
+            Event::RightDoubleQuote => Self::RightDoubleQuote,
+            Event::Ellipsis => Self::Ellipsis,
+            Event::EnDash => Self::EnDash,
+            Event::EmDash => Self::EmDash,
+            Event::NonBreakingSpace => Self::NonBreakingSpace,
+            Event::Softbreak => Self::Softbreak,
+            Event::Hardbreak => Self::Hardbreak,
+            Event::Escape => Self::Escape,
+            Event::Blankline => Self::Blankline,
+            Event::ThematicBreak(attributes) => Self::ThematicBreak {
+                attributes: json_attributes(attributes),
+            },
+            Event::Attributes(attributes) => Self::Attributes {
+                attributes: json_attributes(attributes),
+            },
+        }
+    }
+}
+
+impl JsonEvent {
+    fn into_event(self, strings: &Strings) -> Event<'_> {
+        match self {
+            Self::Start {
+                container,
+                attributes,
+            } => Event::Start(
+                container.into_container(strings),
+                into_attributes(attributes, strings),
+            ),
+            Self::End { container } => Event::End(container.into_container(strings)),
+            Self::Str { text } => Event::Str(text.into()),
+            Self::FootnoteReference { label } => Event::FootnoteReference(strings.alloc(&label)),
+            Self::Symbol { symbol } => Event::Symbol(symbol.into()),
+            Self::LeftSingleQuote => Event::LeftSingleQuote,
+            Self::RightSingleQuote => Event::RightSingleQuote,
+            Self::LeftDoubleQuote => Event::LeftDoubleQuote,
+            Self::RightDoubleQuote => Event::RightDoubleQuote,
+            Self::Ellipsis => Event::Ellipsis,
+            Self::EnDash => Event::EnDash,
+            Self::EmDash => Event::EmDash,
+            Self::NonBreakingSpace => Event::NonBreakingSpace,
+            Self::Softbreak => Event::Softbreak,
+            Self::Hardbreak => Event::Hardbreak,
+            Self::Escape => Event::Escape,
+            Self::Blankline => Event::Blankline,
+            Self::ThematicBreak { attributes } => {
+                Event::ThematicBreak(into_attributes(attributes, strings))
+            }
+            Self::Attributes { attributes } => {
+                Event::Attributes(into_attributes(attributes, strings))
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum JsonContainer {
+    Blockquote,
+    List {
+        #[serde(with = "ListKindDef")]
+        kind: ListKind,
+        tight: bool,
+    },
+    ListItem,
+    TaskListItem {
+        checked: bool,
+    },
+    DescriptionList,
+    DescriptionDetails,
+    Footnote {
+        label: String,
+    },
+    Table,
+    TableRow {
+        head: bool,
+    },
+    Section {
+        id: String,
+    },
+    Div {
+        class: String,
+    },
+    Paragraph,
+    Heading {
+        level: u16,
+        has_section: bool,
+        id: String,
+    },
+    TableCell {
+        #[serde(with = "AlignmentDef")]
+        alignment: Alignment,
+        head: bool,
+    },
+    Caption,
+    DescriptionTerm,
+    LinkDefinition {
+        label: String,
+    },
+    RawBlock {
+        format: String,
+    },
+    CodeBlock {
+        language: String,
+    },
+    Span,
+    Link {
+        destination: String,
+        #[serde(with = "LinkTypeDef")]
+        link_type: LinkType,
+    },
+    Image {
+        source: String,
+        #[serde(with = "SpanLinkTypeDef")]
+        link_type: SpanLinkType,
+    },
+    Verbatim,
+    Math {
+        display: bool,
+    },
+    RawInline {
+        format: String,
+    },
+    Subscript,
+    Superscript,
+    Insert,
+    Delete,
+    Strong,
+    Emphasis,
+    Mark,
+}
+
+impl From<&Container<'_>> for JsonContainer {
+    fn from(container: &Container) -> Self {
+        match container {
+            Container::Blockquote => Self::Blockquote,
+            Container::List { kind, tight } => Self::List {
+                kind: *kind,
+                tight: *tight,
+            },
+            Container::ListItem => Self::ListItem,
+            Container::TaskListItem { checked } => Self::TaskListItem { checked: *checked },
+            Container::DescriptionList => Self::DescriptionList,
+            Container::DescriptionDetails => Self::DescriptionDetails,
+            Container::Footnote { label } => Self::Footnote {
+                label: label.to_string(),
+            },
+            Container::Table => Self::Table,
+            Container::TableRow { head } => Self::TableRow { head: *head },
+            Container::Section { id } => Self::Section { id: id.to_string() },
+            Container::Div { class } => Self::Div {
+                class: class.to_string(),
+            },
+            Container::Paragraph => Self::Paragraph,
+            Container::Heading {
+                level,
+                has_section,
+                id,
+            } => Self::Heading {
+                level: *level,
+                has_section: *has_section,
+                id: id.to_string(),
+            },
+            Container::TableCell { alignment, head } => Self::TableCell {
+                alignment: *alignment,
+                head: *head,
+            },
+            Container::Caption => Self::Caption,
+            Container::DescriptionTerm => Self::DescriptionTerm,
+            Container::LinkDefinition { label } => Self::LinkDefinition {
+                label: label.to_string(),
+            },
+            Container::RawBlock { format } => Self::RawBlock {
+                format: format.to_string(),
+            },
+            Container::CodeBlock { language } => Self::CodeBlock {
+                language: language.to_string(),
+            },
+            Container::Span => Self::Span,
+            Container::Link(destination, link_type) => Self::Link {
+                destination: destination.to_string(),
+                link_type: *link_type,
+            },
+            Container::Image(source, link_type) => Self::Image {
+                source: source.to_string(),
+                link_type: *link_type,
+            },
+            Container::Verbatim => Self::Verbatim,
+            Container::Math { display } => Self::Math { display: *display },
+            Container::RawInline { format } => Self::RawInline {
+                format: format.to_string(),
+            },
+            Container::Subscript => Self::Subscript,
+            Container::Superscript => Self::Superscript,
+            Container::Insert => Self::Insert,
+            Container::Delete => Self::Delete,
+            Container::Strong => Self::Strong,
+            Container::Emphasis => Self::Emphasis,
+            Container::Mark => Self::Mark,
+        }
+    }
+}
+
+impl JsonContainer {
+    fn into_container(self, strings: &Strings) -> Container<'_> {
+        match self {
+            Self::Blockquote => Container::Blockquote,
+            Self::List { kind, tight } => Container::List { kind, tight },
+            Self::ListItem => Container::ListItem,
+            Self::TaskListItem { checked } => Container::TaskListItem { checked },
+            Self::DescriptionList => Container::DescriptionList,
+            Self::DescriptionDetails => Container::DescriptionDetails,
+            Self::Footnote { label } => Container::Footnote {
+                label: strings.alloc(&label),
+            },
+            Self::Table => Container::Table,
+            Self::TableRow { head } => Container::TableRow { head },
+            Self::Section { id } => Container::Section { id: id.into() },
+            Self::Div { class } => Container::Div {
+                class: strings.alloc(&class),
+            },
+            Self::Paragraph => Container::Paragraph,
+            Self::Heading {
+                level,
+                has_section,
+                id,
+            } => Container::Heading {
+                level,
+                has_section,
+                id: id.into(),
+            },
+            Self::TableCell { alignment, head } => Container::TableCell { alignment, head },
+            Self::Caption => Container::Caption,
+            Self::DescriptionTerm => Container::DescriptionTerm,
+            Self::LinkDefinition { label } => Container::LinkDefinition {
+                label: strings.alloc(&label),
+            },
+            Self::RawBlock { format } => Container::RawBlock {
+                format: strings.alloc(&format),
+            },
+            Self::CodeBlock { language } => Container::CodeBlock {
+                language: strings.alloc(&language),
+            },
+            Self::Span => Container::Span,
+            Self::Link {
+                destination,
+                link_type,
+            } => Container::Link(Cow::Owned(destination), link_type),
+            Self::Image { source, link_type } => Container::Image(Cow::Owned(source), link_type),
+            Self::Verbatim => Container::Verbatim,
+            Self::Math { display } => Container::Math { display },
+            Self::RawInline { format } => Container::RawInline {
+                format: strings.alloc(&format),
+            },
+            Self::Subscript => Container::Subscript,
+            Self::Superscript => Container::Superscript,
+            Self::Insert => Container::Insert,
+            Self::Delete => Container::Delete,
+            Self::Strong => Container::Strong,
+            Self::Emphasis => Container::Emphasis,
+            Self::Mark => Container::Mark,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum JsonAttribute {
+    Class { value: String },
+    Id { value: String },
+    Pair { key: String, value: String },
+    Comment { value: String },
+}
+
+fn json_attributes(attributes: &Attributes) -> Vec<JsonAttribute> {
+    attributes
+        .iter()
+        .map(|(kind, value)| {
+            let value = value.to_string();
+
+            match kind {
+                AttributeKind::Class => JsonAttribute::Class { value },
+                AttributeKind::Id => JsonAttribute::Id { value },
+                AttributeKind::Pair { key } => JsonAttribute::Pair {
+                    key: key.to_string(),
+                    value,
+                },
+                AttributeKind::Comment => JsonAttribute::Comment { value },
+            }
+        })
+        .collect()
+}
+
+fn into_attributes(attributes: Vec<JsonAttribute>, strings: &Strings) -> Attributes<'_> {
+    let mut result = Attributes::new();
+
+    for attribute in attributes {
+        let (kind, value) = match attribute {
+            JsonAttribute::Class { value } => (AttributeKind::Class, value),
+            JsonAttribute::Id { value } => (AttributeKind::Id, value),
+            JsonAttribute::Pair { key, value } => (
+                AttributeKind::Pair {
+                    key: strings.alloc(&key),
+                },
+                value,
+            ),
+            JsonAttribute::Comment { value } => (AttributeKind::Comment, value),
+        };
+
+        result.push((kind, AttributeValue::from(value)));
+    }
+
+    result
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(remote = "ListKind", rename_all = "snake_case")]
+enum ListKindDef {
+    Unordered(#[serde(with = "ListBulletTypeDef")] ListBulletType),
+    Ordered {
+        #[serde(with = "OrderedListNumberingDef")]
+        numbering: OrderedListNumbering,
+        #[serde(with = "OrderedListStyleDef")]
+        style: OrderedListStyle,
+        start: u64,
+    },
+    Task(#[serde(with = "ListBulletTypeDef")] ListBulletType),
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(remote = "ListBulletType", rename_all = "snake_case")]
+enum ListBulletTypeDef {
+    Dash,
+    Star,
+    Plus,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(remote = "OrderedListNumbering", rename_all = "snake_case")]
+enum OrderedListNumberingDef {
+    Decimal,
+    AlphaLower,
+    AlphaUpper,
+    RomanLower,
+    RomanUpper,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(remote = "OrderedListStyle", rename_all = "snake_case")]
+enum OrderedListStyleDef {
+    Period,
+    Paren,
+    ParenParen,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(remote = "Alignment", rename_all = "snake_case")]
+enum AlignmentDef {
+    Unspecified,
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(remote = "LinkType", rename_all = "snake_case")]
+enum LinkTypeDef {
+    Span(#[serde(with = "SpanLinkTypeDef")] SpanLinkType),
+    AutoLink,
+    Email,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(remote = "SpanLinkType", rename_all = "snake_case")]
+enum SpanLinkTypeDef {
+    Inline,
+    Reference,
+    Unresolved,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(source: &str) -> Vec<(Event<'_>, Range<usize>)> {
+        jotdown::Parser::new(source).into_offset_iter().collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let source = concat!(
+            "{#intro .lead}\n",
+            "# Heading\n",
+            "\n",
+            "A _paragraph_ with `code`, $`x^2`, [a link](https://example.com),\n",
+            "\"quotes\"... -- and --- :smile: [^note]\\\n",
+            "break.\n",
+            "\n",
+            "1) one\n",
+            "2) two\n",
+            "\n",
+            "| a | b |\n",
+            "|:--|--:|\n",
+            "| 1 | 2 |\n",
+            "\n",
+            "``` rust\n",
+            "fn main() {}\n",
+            "```\n",
+            "\n",
+            "* * *\n",
+            "\n",
+            "[^note]: A footnote.\n",
+        );
+        let events = events(source);
+
+        let json = events_to_json(&events).unwrap();
+        assert_eq!(events_from_json(&json, &Strings::new()).unwrap(), events);
+    }
+
+    #[test]
+    fn json_format() {
+        let events = events("``` rust\nx\n```\n");
+        let json: serde_json::Value =
+            serde_json::from_str(&events_to_json(&events[..1]).unwrap()).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "type": "start",
+                "container": {"type": "code_block", "language": "rust"},
+                "attributes": [],
+                "range": {"start": 0, "end": 9},
+            }])
+        );
+    }
+
+    #[test]
+    fn missing_ranges() {
+        let json = r#"[
+            {"type": "start", "container": {"type": "paragraph"}, "range": {"start": 3, "end": 4}},
+            {"type": "str", "text": "inserted"},
+            {"type": "end", "container": {"type": "paragraph"}, "range": {"start": 5, "end": 6}}
+        ]"#;
+
+        let ranges: Vec<_> = events_from_json(json, &Strings::new())
+            .unwrap()
+            .into_iter()
+            .map(|(_, range)| range)
+            .collect();
+        assert_eq!(ranges, [3..4, 3..4, 5..6]);
+    }
+
+    #[test]
+    fn invalid_events() {
+        assert!(events_from_json(r#"[{"type": "unknown"}]"#, &Strings::new()).is_err());
+    }
+}
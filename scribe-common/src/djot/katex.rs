@@ -1,14 +1,43 @@
-use jotdown::{Container, Event};
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Range;
+
+use jotdown::{AttributeKind, Attributes, Container, Event};
 use katex::Opts;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tracing::trace;
+use tracing::{debug, trace, warn};
+
+use super::error::{Diagnostics, Located};
+use super::html::escape_html;
+use super::macros::global_macros;
+use crate::cache::{Cache, cache_key};
+use crate::tools::latex::{InlineSvg, LatexError, LatexSnippet};
+use crate::tools::svg::{SharedSvgProcessor, SvgProcessor};
+
+/// Preamble for math rendered with LaTeX by [`MathFallback::Latex`].
+pub const DEFAULT_LATEX_PREAMBLE: &str = "\\documentclass{article}\n\\usepackage{amsmath,amssymb}";
+
+/// An event with its range in the source.
+type SpannedEvent<'a> = (Event<'a>, Range<usize>);
 
 /// Render math to HTML using KaTeX.
 #[derive(Debug, Clone)]
 pub struct KatexMath<'a, I> {
     inner: I,
     opts: Opts,
+    opts_key: String,
+    macros: BTreeMap<String, String>,
+    persistence: MacroPersistence,
+    cache: Option<KatexCache>,
+    fallbacks: Vec<MathFallback>,
+    latex_preamble: String,
+    svg: SharedSvgProcessor,
+    source: Option<&'a str>,
+    diagnostics: Option<Diagnostics>,
+    lookahead: VecDeque<SpannedEvent<'a>>,
     buffer: Vec<Event<'a>>,
+    /// The range of the element that the buffered events replace.
+    range: Range<usize>,
 }
 
 impl<'a, I> KatexMath<'a, I> {
@@ -16,70 +45,537 @@ impl<'a, I> KatexMath<'a, I> {
         Self {
             inner,
             opts,
-            buffer: Vec::with_capacity(2),
+            opts_key: String::new(),
+            macros: BTreeMap::new(),
+            persistence: MacroPersistence::default(),
+            cache: None,
+            fallbacks: Vec::new(),
+            latex_preamble: DEFAULT_LATEX_PREAMBLE.into(),
+            svg: SharedSvgProcessor::new(SvgProcessor::new("math")),
+            source: None,
+            diagnostics: None,
+            lookahead: VecDeque::new(),
+            buffer: Vec::with_capacity(4),
+            range: 0..0,
+        }
+    }
+
+    /// Add macros for all math in the document.
+    ///
+    /// Prefer this over setting the macros in the [`Opts`], so that they can
+    /// be taken into account by the [`KatexCache`].
+    pub fn with_macros(mut self, macros: impl IntoIterator<Item = (String, String)>) -> Self {
+        for (name, body) in macros {
+            self.add_macro(name, body);
+        }
+
+        self
+    }
+
+    /// Reuse renderings from the given cache.
+    ///
+    /// The [`Opts`] cannot be serialized, so `opts` has to identify them in
+    /// the keys of the cache instead, e.g. the configuration they were built
+    /// from.
+    pub fn with_cache(mut self, cache: KatexCache, opts: impl Serialize) -> Self {
+        self.cache = Some(cache);
+        self.opts_key = cache_key(opts);
+        self
+    }
+
+    /// Set what to try, in order, when KaTeX fails to render math.
+    pub fn with_fallbacks(mut self, fallbacks: impl IntoIterator<Item = MathFallback>) -> Self {
+        self.fallbacks = fallbacks.into_iter().collect();
+        self
+    }
+
+    /// Set the preamble for [`MathFallback::Latex`].
+    pub fn with_latex_preamble(mut self, preamble: impl Into<String>) -> Self {
+        self.latex_preamble = preamble.into();
+        self
+    }
+
+    /// Process the SVGs of [`MathFallback::Latex`] with a processor shared by
+    /// the page.
+    pub fn with_svg_processor(mut self, svg: SharedSvgProcessor) -> Self {
+        self.svg = svg;
+        self
+    }
+
+    /// Set the source that the ranges of the events refer to, so that errors
+    /// of [`MathFallback::Latex`] are reported at the lines of the math.
+    pub fn with_source(mut self, source: &'a str) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Record the math rendered by a fallback as warnings instead of logging
+    /// them.
+    pub fn with_diagnostics(mut self, diagnostics: Diagnostics) -> Self {
+        self.diagnostics = Some(diagnostics);
+        self
+    }
+
+    /// Set which macro definitions persist from one math block to the next.
+    ///
+    /// Only the definitions in math that KaTeX rendered persist, so that
+    /// those of math it failed on do not break later math.
+    pub fn with_macro_persistence(mut self, persistence: MacroPersistence) -> Self {
+        self.persistence = persistence;
+        self
+    }
+
+    fn persist_macros(&mut self, math: &str) {
+        let newcommand = match self.persistence {
+            MacroPersistence::None => return,
+            MacroPersistence::Global => false,
+            MacroPersistence::All => true,
+        };
+
+        for (name, body) in global_macros(math, newcommand) {
+            trace!("persisting macro `{}`", name);
+            self.add_macro(name, body);
+        }
+    }
+
+    fn add_macro(&mut self, name: String, body: String) {
+        self.macros.insert(name.clone(), body.clone());
+        self.opts.add_macro(name, body);
+    }
+
+    /// The key of a rendering in the [`KatexCache`].
+    fn cache_key(&self, math: &str, display: bool) -> String {
+        cache_key((
+            katex::KATEX_VERSION,
+            &self.opts_key,
+            &self.macros,
+            display,
+            math,
+        ))
+    }
+
+    fn render(&mut self, math: &str, display: bool) -> Result<String, katex::Error> {
+        self.opts.set_display_mode(display);
+
+        let Some(cache) = &self.cache else {
+            return katex::render_with_opts(math, &self.opts);
+        };
+
+        let key = self.cache_key(math, display);
+
+        if let Some(rendered) = cache.get(&key) {
+            trace!("using cached rendering");
+            return Ok(rendered);
+        }
+
+        let rendered = katex::render_with_opts(math, &self.opts)?;
+        cache.insert(key, rendered.clone());
+        Ok(rendered)
+    }
+
+    /// Render math that KaTeX failed on with the configured fallbacks.
+    ///
+    /// If all fallbacks fail, the error of the LaTeX fallback is returned
+    /// together with the KaTeX error.
+    fn render_fallback(
+        &mut self,
+        math: &str,
+        display: bool,
+        error: katex::Error,
+    ) -> Result<String, KatexMathError> {
+        let mut latex_error = None;
+
+        for fallback in self.fallbacks.clone() {
+            match fallback {
+                MathFallback::Latex => match self.render_latex(math, display) {
+                    Ok(rendered) => {
+                        self.warn(format!("rendered math with latex, katex failed: {}", error));
+                        return Ok(rendered);
+                    }
+                    Err(error) => {
+                        debug!("latex fallback failed: {}", error);
+                        latex_error = Some(error);
+                    }
+                },
+                MathFallback::Client => {
+                    self.warn(format!("left math to the client, katex failed: {}", error));
+                    return Ok(escape_html(&wrap_math(math, display)));
+                }
+            }
         }
+
+        Err(match latex_error {
+            Some(latex) => KatexMathError::Latex {
+                katex: error,
+                latex,
+            },
+            None => error.into(),
+        })
+    }
+
+    /// Warn about the current element.
+    fn warn(&self, message: String) {
+        match &self.diagnostics {
+            Some(diagnostics) => diagnostics.warn(&message, self.range.clone()),
+            None => warn!("{}", message),
+        }
+    }
+
+    fn render_latex(&mut self, math: &str, display: bool) -> Result<String, LatexError> {
+        // Make the KaTeX macros available to LaTeX as well.
+        let mut preamble = self.latex_preamble.clone();
+
+        for (name, body) in &self.macros {
+            let arity = (1..=9)
+                .rev()
+                .find(|n| body.contains(&format!("#{}", n)))
+                .unwrap_or(0);
+            let parameters: String = (1..=arity).map(|n| format!("#{}", n)).collect();
+            preamble.push_str(&format!("\n\\def{}{}{{{}}}", name, parameters, body));
+        }
+
+        let body = match display {
+            true => format!("$\\displaystyle {}$", math),
+            false => format!("${}$", math),
+        };
+
+        // The math starts on the line of its element in the note.
+        let line = self.source.map_or(1, |source| {
+            source[..self.range.start.min(source.len())]
+                .matches('\n')
+                .count()
+                + 1
+        });
+
+        let svg = LatexSnippet::new(&preamble, &body, line).to_inline_svg_blocking()?;
+        let svg = InlineSvg {
+            svg: self.svg.process(&svg.svg),
+            ..svg
+        };
+
+        Ok(svg.to_html())
     }
 }
 
-impl<'a, I> Iterator for KatexMath<'a, I>
+/// What to do when KaTeX fails to render math.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MathFallback {
+    /// Render the math with LaTeX to an SVG.
+    ///
+    /// Requires the tools of [`latex_to_svg`](crate::tools::latex::latex_to_svg).
+    Latex,
+    /// Leave the TeX source in the `.math` element for a renderer on the client.
+    ///
+    /// The source is delimited with `\(...\)` or `\[...\]`.
+    Client,
+}
+
+/// Add TeX math delimiters.
+fn wrap_math(math: &str, display: bool) -> String {
+    match display {
+        true => format!("\\[{}\\]", math),
+        false => format!("\\({}\\)", math),
+    }
+}
+
+/// Cache for math rendered by [`KatexMath`].
+///
+/// Renderings are keyed by the expression, the display mode, the options, the
+/// effective macros and the version of KaTeX.
+pub type KatexCache = Cache<String>;
+
+/// Which macro definitions in math persist through the rest of the document.
+///
+/// Definitions are accumulated in document order, so that notation can be
+/// defined in place and used in all math further down.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MacroPersistence {
+    /// Every math block is rendered independently.
+    #[default]
+    None,
+    /// Definitions with `\gdef` and `\global\def` persist.
+    Global,
+    /// Like [`MacroPersistence::Global`], and `\newcommand` persists as well.
+    All,
+}
+
+impl<'a, I> KatexMath<'a, I>
 where
-    I: Iterator<Item = Event<'a>>,
+    I: Iterator<Item = SpannedEvent<'a>>,
 {
-    type Item = Result<Event<'a>, KatexMathError>;
+    fn pull(&mut self) -> Option<SpannedEvent<'a>> {
+        self.lookahead.pop_front().or_else(|| self.inner.next())
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Some(event) = self.buffer.pop() {
-            return Some(Ok(event));
-        };
+    /// Put back events to be pulled again, in the same order.
+    fn unread(&mut self, events: impl IntoIterator<Item = SpannedEvent<'a>>) {
+        let events: Vec<_> = events.into_iter().collect();
 
-        let (display, attributes) = match self.inner.next()? {
-            Event::Start(Container::Math { display }, attributes) => (display, attributes),
-            event => return Some(Ok(event)),
+        for event in events.into_iter().rev() {
+            self.lookahead.push_front(event);
+        }
+    }
+
+    /// Collect the text of a math element, up to and including its end.
+    fn collect_math(&mut self) -> Result<String, KatexMathError> {
+        let mut math = String::new();
+
+        loop {
+            match self.pull() {
+                Some((Event::End(_), range)) => {
+                    self.range.end = self.range.end.max(range.end);
+                    return Ok(math);
+                }
+                Some((Event::Str(str), _)) => math.push_str(&str),
+                Some(_) => return Err(KatexMathError::Unexpected),
+                None => return Ok(math),
+            }
+        }
+    }
+
+    /// Check whether a paragraph consists of display math only.
+    ///
+    /// Returns the attributes and text of the math in that case, and consumes
+    /// the rest of the paragraph. Otherwise the events are left to be read.
+    fn standalone_display_math(&mut self) -> Option<(Attributes<'a>, Vec<SpannedEvent<'a>>)> {
+        let mut events = Vec::new();
+
+        let (attributes, start_range) = match self.pull()? {
+            (Event::Start(Container::Math { display: true }, attributes), range) => {
+                (attributes, range)
+            }
+            event => {
+                self.unread([event]);
+                return None;
+            }
         };
 
+        loop {
+            let event = self.pull();
+            let end = matches!(event, Some((Event::End(Container::Math { .. }), _)) | None);
+            events.extend(event);
+
+            if end {
+                break;
+            }
+        }
+
+        match self.pull() {
+            Some((Event::End(Container::Paragraph), range)) => {
+                self.range.end = range.end;
+                Some((attributes, events))
+            }
+            event => {
+                let start = (
+                    Event::Start(Container::Math { display: true }, attributes),
+                    start_range,
+                );
+                self.unread(std::iter::once(start).chain(events).chain(event));
+                None
+            }
+        }
+    }
+
+    /// Render math and queue it up, wrapped in an element with the attributes.
+    ///
+    /// Inline math is rendered as inline HTML in a span. Display math is
+    /// rendered as a block in a div when `block` is set, and as inline HTML in
+    /// a span otherwise.
+    fn emit_math(
+        &mut self,
+        display: bool,
+        block: bool,
+        mut attributes: Attributes<'a>,
+    ) -> Result<Event<'a>, KatexMathError> {
+        let math = self.collect_math()?;
+
         if display {
             trace!("found display math");
         } else {
             trace!("found inline math");
         }
 
-        let mut math = String::new();
-
-        loop {
-            match self.inner.next()? {
-                Event::End(_) => break,
-                Event::Str(str) => math.push_str(&str),
-                _ => return Some(Err(KatexMathError::Unexpected)),
+        let rendered = match self.render(&math, display) {
+            Ok(rendered) => {
+                self.persist_macros(&math);
+                rendered
             }
-        }
-
-        self.opts.set_display_mode(display);
-        let result = katex::render_with_opts(&math, &self.opts);
+            Err(err) => self.render_fallback(&math, display, err)?,
+        };
 
-        let rendered = match result {
-            Ok(rendered) => rendered,
-            Err(err) => return Some(Err(err.into())),
+        let (wrapper, raw) = match block {
+            true => (
+                Container::Div {
+                    class: "math display",
+                },
+                Container::RawBlock { format: "html" },
+            ),
+            false => {
+                let class = match display {
+                    true => "math display",
+                    false => "math inline",
+                };
+                attributes.push((AttributeKind::Class, class.into()));
+                (Container::Span, Container::RawInline { format: "html" })
+            }
         };
 
         self.buffer.extend([
-            Event::End(Container::RawBlock { format: "html" }),
+            Event::End(wrapper.clone()),
+            Event::End(raw.clone()),
             Event::Str(rendered.into()),
+            Event::Start(raw, Attributes::new()),
         ]);
 
-        Some(Ok(Event::Start(
-            Container::RawBlock { format: "html" },
-            attributes,
-        )))
+        Ok(Event::Start(wrapper, attributes))
+    }
+}
+
+impl<'a, I> Iterator for KatexMath<'a, I>
+where
+    I: Iterator<Item = SpannedEvent<'a>>,
+{
+    type Item = Result<SpannedEvent<'a>, Located<KatexMathError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.buffer.pop() {
+            return Some(Ok((event, self.range.clone())));
+        };
+
+        let (event, range) = self.pull()?;
+        self.range = range;
+
+        let result = match event {
+            Event::Start(Container::Math { display }, attributes) => {
+                self.emit_math(display, false, attributes)
+            }
+            Event::Start(Container::Paragraph, attributes) => {
+                match self.standalone_display_math() {
+                    Some((math_attributes, events)) => {
+                        // Attributes of the paragraph and the math end up on the div.
+                        let mut attributes = attributes;
+                        attributes.extend(math_attributes.iter().cloned());
+
+                        // Put the math back to be read by `emit_math`.
+                        self.unread(events);
+                        self.emit_math(true, true, attributes)
+                    }
+                    None => Ok(Event::Start(Container::Paragraph, attributes)),
+                }
+            }
+            event => Ok(event),
+        };
+
+        Some(match result {
+            Ok(event) => Ok((event, self.range.clone())),
+            Err(error) => Err(Located::new(error, self.range.clone())),
+        })
     }
 }
 
 /// Error produced by [`KatexMath`].
-#[derive(Debug, Clone, Error)]
+#[derive(Debug, Error)]
 pub enum KatexMathError {
     /// Error while rendering KaTeX math.
     #[error("failed to render katex math: {0}")]
     Katex(#[from] katex::Error),
+    /// Error while rendering math with LaTeX, after KaTeX failed as well.
+    #[error("failed to render katex math: {katex}; latex fallback failed: {latex}")]
+    Latex {
+        katex: katex::Error,
+        #[source]
+        latex: LatexError,
+    },
     /// Unexpected [`Event`] in math block.
     #[error("unexpected event in math block")]
     Unexpected,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::djot::Severity;
+
+    fn render(source: &str) -> String {
+        let events = jotdown::Parser::new(source).into_offset_iter();
+        let events = KatexMath::new(events, Opts::default()).map(|result| result.unwrap().0);
+        jotdown::html::render_to_string(events)
+    }
+
+    /// The contents of the paragraphs in the HTML.
+    fn paragraphs(html: &str) -> Vec<&str> {
+        html.split("<p>")
+            .skip(1)
+            .map(|rest| rest.split_once("</p>").unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn standalone_display_math_is_a_block() {
+        let html = render("$$`x^2`{#eq .big}\n");
+
+        assert!(html.starts_with("<div id=\"eq\" class=\"big math display\">"));
+        assert!(paragraphs(&html).is_empty());
+    }
+
+    #[test]
+    fn display_math_in_text_stays_in_the_paragraph() {
+        let html = render("so $$`x^2` holds\n");
+        let paragraphs = paragraphs(&html);
+
+        assert_eq!(paragraphs.len(), 1);
+        assert!(paragraphs[0].starts_with("so <span class=\"math display\">"));
+        assert!(paragraphs[0].ends_with("</span> holds"));
+        assert!(!paragraphs[0].contains("<div"));
+    }
+
+    #[test]
+    fn inline_math_stays_in_the_paragraph() {
+        let html = render("$`x` and $`y`\n");
+        let paragraphs = paragraphs(&html);
+
+        assert_eq!(paragraphs.len(), 1);
+        assert_eq!(
+            paragraphs[0]
+                .matches("<span class=\"math inline\">")
+                .count(),
+            2
+        );
+        assert!(!paragraphs[0].contains("<div"));
+    }
+
+    #[test]
+    fn client_fallback_is_recorded_as_warning() {
+        let diagnostics = Diagnostics::new();
+        let source = "ok $`x`, not $`\\foo`\n";
+        let events = jotdown::Parser::new(source).into_offset_iter();
+        let events = KatexMath::new(events, Opts::default())
+            .with_fallbacks([MathFallback::Client])
+            .with_diagnostics(diagnostics.clone())
+            .map(|result| result.unwrap().0);
+        let html = jotdown::html::render_to_string(events);
+
+        assert!(html.contains("\\(\\foo\\)"));
+
+        let diagnostics = diagnostics.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(&source[diagnostics[0].range.clone()], "$`\\foo`");
+    }
+
+    #[test]
+    fn macros_of_failed_math_do_not_persist() {
+        let diagnostics = Diagnostics::new();
+        let source = "$`\\gdef\\a{1} \\foo` $`\\a`\n";
+        let events = jotdown::Parser::new(source).into_offset_iter();
+        let events = KatexMath::new(events, Opts::default())
+            .with_macro_persistence(MacroPersistence::Global)
+            .with_fallbacks([MathFallback::Client])
+            .with_diagnostics(diagnostics.clone());
+
+        assert!(events.into_iter().all(|result| result.is_ok()));
+        assert_eq!(diagnostics.diagnostics().len(), 2);
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::{HashMap, HashSet};
+
+use thiserror::Error;
+
+/// Parse KaTeX macros from TeX definitions.
+///
+/// Supports `\newcommand`, `\renewcommand`, `\providecommand`, `\def`,
+/// `\gdef`, `\global\def` and `\DeclareMathOperator`. Comments starting with
+/// `%` are ignored. The returned map can be passed to KaTeX as is: keys are
+/// control sequences including the backslash, and arguments are referred to
+/// as `#1`, `#2`, ....
+pub fn parse_macros(source: &str) -> Result<HashMap<String, String>, MacroError> {
+    let mut parser = MacroParser { source, pos: 0 };
+    let mut macros = HashMap::new();
+
+    loop {
+        parser.skip_whitespace();
+
+        if parser.is_done() {
+            break;
+        }
+
+        let command = parser.control_sequence()?;
+
+        let Some((name, body)) = parser.definition(command)? else {
+            return Err(parser.error(format!("unsupported command `{}`", command)));
+        };
+
+        macros.insert(name.to_string(), body);
+    }
+
+    Ok(macros)
+}
+
+/// Extract the macro definitions from a math expression that persist beyond it.
+///
+/// These are definitions with `\gdef` and `\global\def`, and with
+/// `\newcommand`, `\renewcommand` and `\providecommand` if `newcommand` is set.
+/// Malformed definitions are skipped, KaTeX reports them when rendering.
+pub fn global_macros(source: &str, newcommand: bool) -> Vec<(String, String)> {
+    let mut parser = MacroParser { source, pos: 0 };
+    let mut macros = Vec::new();
+
+    while let Some(start) = parser.rest().find('\\') {
+        parser.pos += start;
+
+        let Ok(command) = parser.control_sequence() else {
+            parser.pos += 1;
+            continue;
+        };
+
+        let persists = match command {
+            "\\gdef" | "\\global" => true,
+            "\\newcommand" | "\\renewcommand" | "\\providecommand" => newcommand,
+            _ => false,
+        };
+
+        if !persists {
+            continue;
+        }
+
+        let pos = parser.pos;
+
+        match parser.definition(command) {
+            Ok(Some((name, body))) => macros.push((name.to_string(), body)),
+            _ => parser.pos = pos,
+        }
+    }
+
+    macros
+}
+
+/// Check that no macro expands to itself.
+pub fn check_macros(macros: &HashMap<String, String>) -> Result<(), MacroError> {
+    let mut done = HashSet::new();
+
+    for name in macros.keys() {
+        let mut path = Vec::new();
+        visit_macro(macros, name, &mut path, &mut done)?;
+    }
+
+    Ok(())
+}
+
+fn visit_macro<'a>(
+    macros: &'a HashMap<String, String>,
+    name: &'a str,
+    path: &mut Vec<&'a str>,
+    done: &mut HashSet<&'a str>,
+) -> Result<(), MacroError> {
+    if done.contains(name) {
+        return Ok(());
+    }
+
+    if let Some(start) = path.iter().position(|visited| *visited == name) {
+        let mut cycle: Vec<String> = path[start..].iter().map(|name| name.to_string()).collect();
+        cycle.push(name.to_string());
+        return Err(MacroError::Recursive(cycle));
+    }
+
+    let Some((name, body)) = macros.get_key_value(name) else {
+        return Ok(());
+    };
+
+    path.push(name);
+
+    for used in control_sequences(body) {
+        visit_macro(macros, used, path, done)?;
+    }
+
+    path.pop();
+    done.insert(name);
+    Ok(())
+}
+
+/// The control sequences used in a macro body.
+fn control_sequences(body: &str) -> impl Iterator<Item = &str> {
+    body.match_indices('\\').filter_map(|(start, _)| {
+        let rest = &body[start + 1..];
+        let len = match rest.find(|c: char| !c.is_ascii_alphabetic()) {
+            Some(0) => rest.chars().next()?.len_utf8(),
+            Some(len) => len,
+            None => rest.len(),
+        };
+        Some(&body[start..start + 1 + len])
+    })
+}
+
+struct MacroParser<'a> {
+    source: &'a str,
+    pos: usize,
+}
+
+impl<'a> MacroParser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.source[self.pos..]
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos >= self.source.len()
+    }
+
+    fn skip_whitespace(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+
+            if !trimmed.starts_with('%') {
+                break;
+            }
+
+            self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+
+        if self.rest().starts_with(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn control_sequence(&mut self) -> Result<&'a str, MacroError> {
+        self.skip_whitespace();
+
+        let start = self.pos;
+        let rest = self.rest();
+
+        let Some(name) = rest.strip_prefix('\\') else {
+            return Err(self.error("expected a control sequence"));
+        };
+
+        let len = match name.find(|c: char| !c.is_ascii_alphabetic()) {
+            Some(0) => name.chars().next().map_or(0, char::len_utf8),
+            Some(len) => len,
+            None => name.len(),
+        };
+
+        if len == 0 {
+            return Err(self.error("expected a control sequence"));
+        }
+
+        self.pos += 1 + len;
+        Ok(&self.source[start..self.pos])
+    }
+
+    /// The definition introduced by `command`, if `command` defines a macro.
+    fn definition(&mut self, command: &str) -> Result<Option<(&'a str, String)>, MacroError> {
+        let definition = match command {
+            "\\newcommand" | "\\renewcommand" | "\\providecommand" => {
+                self.eat('*');
+                let name = self.macro_name()?;
+
+                if self.optional_argument()?.is_some() && self.optional_argument()?.is_some() {
+                    return Err(self.error("default arguments are not supported"));
+                }
+
+                (name, self.group()?.to_string())
+            }
+            "\\def" | "\\gdef" => {
+                let name = self.control_sequence()?;
+
+                // Skip the parameter text, e.g. `#1#2`.
+                while !self.is_done() && !self.rest().starts_with('{') {
+                    self.pos += self.rest().chars().next().map_or(0, char::len_utf8);
+                }
+
+                (name, self.group()?.to_string())
+            }
+            "\\global" => {
+                let command = self.control_sequence()?;
+
+                if command != "\\def" {
+                    return Ok(None);
+                }
+
+                return self.definition(command);
+            }
+            "\\DeclareMathOperator" => {
+                let star = self.eat('*');
+                let name = self.macro_name()?;
+                let text = self.group()?;
+
+                match star {
+                    true => (name, format!("\\operatorname*{{{}}}", text)),
+                    false => (name, format!("\\operatorname{{{}}}", text)),
+                }
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(definition))
+    }
+
+    /// A macro name, either as `\name` or `{\name}`.
+    fn macro_name(&mut self) -> Result<&'a str, MacroError> {
+        if !self.eat('{') {
+            return self.control_sequence();
+        }
+
+        let name = self.control_sequence()?;
+
+        if !self.eat('}') {
+            return Err(self.error("expected `}` after macro name"));
+        }
+
+        Ok(name)
+    }
+
+    fn optional_argument(&mut self) -> Result<Option<&'a str>, MacroError> {
+        if !self.eat('[') {
+            return Ok(None);
+        }
+
+        let Some(end) = self.rest().find(']') else {
+            return Err(self.error("unclosed `[`"));
+        };
+
+        let argument = &self.rest()[..end];
+        self.pos += end + 1;
+        Ok(Some(argument))
+    }
+
+    /// A balanced group in braces, without the outer braces.
+    fn group(&mut self) -> Result<&'a str, MacroError> {
+        if !self.eat('{') {
+            return Err(self.error("expected `{`"));
+        }
+
+        let start = self.pos;
+        let mut depth = 0;
+        let mut chars = self.rest().char_indices();
+
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    chars.next();
+                }
+                '{' => depth += 1,
+                '}' if depth == 0 => {
+                    self.pos = start + index + 1;
+                    return Ok(&self.source[start..start + index]);
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+
+        Err(self.error("unclosed `{`"))
+    }
+
+    fn error(&self, message: impl Into<String>) -> MacroError {
+        let line = self.source[..self.pos].matches('\n').count() + 1;
+        MacroError::Parse {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+/// Error produced while processing math macros.
+#[derive(Debug, Clone, Error)]
+pub enum MacroError {
+    /// Error while parsing macro definitions.
+    #[error("failed to parse macros on line {line}: {message}")]
+    Parse { line: usize, message: String },
+    /// A macro that expands to itself.
+    #[error("recursive macro definition: {}", .0.join(" -> "))]
+    Recursive(Vec<String>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn macros(definitions: &[(&str, &str)]) -> HashMap<String, String> {
+        definitions
+            .iter()
+            .map(|(name, body)| (name.to_string(), body.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_definitions() {
+        let source = r"
+            % Sets
+            \newcommand{\R}{\mathbb{R}}
+            \renewcommand*\norm[1]{\lVert #1 \rVert}
+            \def\abs#1{\lvert #1 \rvert}
+            \global\def\e{\mathrm{e}}
+            \DeclareMathOperator*{\argmax}{arg\,max}
+        ";
+
+        assert_eq!(
+            parse_macros(source).unwrap(),
+            macros(&[
+                (r"\R", r"\mathbb{R}"),
+                (r"\norm", r"\lVert #1 \rVert"),
+                (r"\abs", r"\lvert #1 \rvert"),
+                (r"\e", r"\mathrm{e}"),
+                (r"\argmax", r"\operatorname*{arg\,max}"),
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_nested_groups() {
+        let source = r"\newcommand{\set}[1]{\left\{ #1 \right\}}";
+
+        assert_eq!(
+            parse_macros(source).unwrap(),
+            macros(&[(r"\set", r"\left\{ #1 \right\}")])
+        );
+    }
+
+    #[test]
+    fn parse_errors_report_the_line() {
+        let error = parse_macros("\\newcommand{\\a}{a}\n\\newcommand{\\b}{b").unwrap_err();
+        assert!(matches!(error, MacroError::Parse { line: 2, .. }));
+
+        let error = parse_macros("\\newcommand{\\a}{a}\n\\frac{1}{2}").unwrap_err();
+        assert!(matches!(error, MacroError::Parse { line: 2, .. }));
+    }
+
+    #[test]
+    fn parse_rejects_default_arguments() {
+        assert!(parse_macros(r"\newcommand{\a}[1][x]{#1}").is_err());
+    }
+
+    #[test]
+    fn check_accepts_macros_using_others() {
+        let macros = macros(&[(r"\a", r"\b + \b"), (r"\b", r"\c"), (r"\c", r"x")]);
+        assert!(check_macros(&macros).is_ok());
+    }
+
+    #[test]
+    fn check_rejects_self_reference() {
+        let macros = macros(&[(r"\a", r"\a + 1")]);
+
+        let Err(MacroError::Recursive(cycle)) = check_macros(&macros) else {
+            panic!("expected a cycle");
+        };
+        assert_eq!(cycle, [r"\a", r"\a"]);
+    }
+
+    #[test]
+    fn check_rejects_indirect_cycles() {
+        let macros = macros(&[(r"\a", r"\b"), (r"\b", r"\c"), (r"\c", r"\frac{\a}{2}")]);
+
+        let Err(MacroError::Recursive(cycle)) = check_macros(&macros) else {
+            panic!("expected a cycle");
+        };
+        assert_eq!(cycle.len(), 4);
+        assert_eq!(cycle.first(), cycle.last());
+    }
+
+    #[test]
+    fn check_does_not_confuse_prefixes() {
+        // `\ab` uses `\a`, not itself.
+        let macros = macros(&[(r"\ab", r"\a b"), (r"\a", r"x")]);
+        assert!(check_macros(&macros).is_ok());
+    }
+}
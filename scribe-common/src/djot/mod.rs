@@ -1,13 +1,39 @@
 //! Utilities to process djot documents.
 
+mod diagram;
+mod equations;
 mod error;
+mod exec;
+mod external;
+mod filter;
 mod frontmatter;
 mod headings;
+mod html;
+mod include;
 mod inkjet;
+mod json;
 mod katex;
+mod macros;
+mod strings;
+mod theme;
 
-pub use error::ShowErrors;
-pub use frontmatter::{parse_frontmatter, split_frontmatter};
-pub use headings::DemoteHeadings;
-pub use inkjet::{InkjetCode, InkjetCodeError};
-pub use katex::{KatexMath, KatexMathError};
+pub use diagram::{DiagramBlocks, DiagramError};
+pub use equations::{EquationError, NumberEquations};
+pub use error::{Diagnostic, Diagnostics, ErrorStyle, Located, Severity, ShowErrors};
+pub use exec::{DEFAULT_OUTPUT_LANGUAGE, ExecCode, ExecCodeError};
+pub use external::{ExternalFilter, ExternalFilterError};
+pub use filter::{Events, Filter, Pipeline, PipelineConfig, PipelineError};
+pub use frontmatter::{
+    Frontmatter, FrontmatterError, FrontmatterFormat, SplitFrontmatter, parse_frontmatter,
+    parse_frontmatter_with_defaults, split_frontmatter,
+};
+pub use headings::{DemoteHeadings, DemoteHeadingsFilter};
+pub use include::{IncludeCode, IncludeError, IncludedFiles};
+pub use inkjet::{InkjetCode, InkjetCodeError, UnsupportedLanguages};
+pub use json::{events_from_json, events_to_json};
+pub use katex::{
+    DEFAULT_LATEX_PREAMBLE, KatexCache, KatexMath, KatexMathError, MacroPersistence, MathFallback,
+};
+pub use macros::{MacroError, check_macros, global_macros, parse_macros};
+pub use strings::Strings;
+pub use theme::{THEMES, ThemeError, load_theme, theme_css};
@@ -0,0 +1,47 @@
+use std::fmt::Debug;
+
+use typed_arena::Arena;
+
+/// Strings that jotdown borrows from the source, like classes or languages,
+/// for events that do not come from the source.
+///
+/// Events from external filters or scripts borrow their strings from here, so
+/// the strings live as long as the `Strings` that they are stored in. Keep it
+/// for one build, so that the strings are freed once the build is done.
+#[derive(Default)]
+pub struct Strings {
+    arena: Arena<u8>,
+}
+
+impl Strings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store a copy of `string`.
+    pub fn alloc(&self, string: &str) -> &str {
+        self.arena.alloc_str(string)
+    }
+}
+
+impl Debug for Strings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Strings")
+            .field("bytes", &self.arena.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_outlive_their_source() {
+        let strings = Strings::new();
+        let rust = strings.alloc(&String::from("rust"));
+        let python = strings.alloc(&String::from("python"));
+
+        assert_eq!((rust, python), ("rust", "python"));
+    }
+}
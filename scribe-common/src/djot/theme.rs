@@ -0,0 +1,407 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use inkjet::constants::{HIGHLIGHT_CLASS_NAMES, HIGHLIGHT_NAMES};
+use inkjet::theme::{Modifier, Style, Theme, vendored};
+use thiserror::Error;
+
+/// Load a highlighting theme.
+///
+/// `name` is either the name of a theme vendored by Inkjet, e.g.
+/// `github_light` (see [`THEMES`]), or the path to a Helix theme file.
+pub fn load_theme(name: &str) -> Result<Theme, ThemeError> {
+    let data = match vendored_theme(name) {
+        Some(data) => data.to_string(),
+        None if name.ends_with(".toml") => {
+            std::fs::read_to_string(name).map_err(|source| ThemeError::Io {
+                path: PathBuf::from(name),
+                source,
+            })?
+        }
+        None => return Err(ThemeError::Unknown(name.to_string())),
+    };
+
+    Theme::from_helix(&data).map_err(|source| ThemeError::Invalid {
+        name: name.to_string(),
+        source,
+    })
+}
+
+/// Generate the stylesheet for code highlighted by [`InkjetCode`](super::InkjetCode).
+///
+/// Colors and font styles are defined as CSS variables on `:root`, and the
+/// rules for the highlight classes refer to them. With a `dark` theme, its
+/// variables apply when the browser prefers a dark color scheme, unless the
+/// root element has `data-theme="light"`. Setting `data-theme="dark"` selects
+/// the dark theme regardless of the preference.
+pub fn theme_css(light: &Theme, dark: Option<&Theme>) -> String {
+    let light_variables = variables(light);
+    let mut dark_variables = dark.map(variables).unwrap_or_default();
+
+    // Reset the variables that only the light theme sets, so that these
+    // classes inherit the dark text color instead.
+    if dark.is_some() {
+        for name in light_variables.keys() {
+            dark_variables
+                .entry(name.clone())
+                .or_insert_with(|| "initial".to_string());
+        }
+    }
+
+    let mut css = String::new();
+    push_variables(&mut css, ":root", &light_variables, "");
+
+    if !dark_variables.is_empty() {
+        css.push_str("\n@media (prefers-color-scheme: dark) {\n");
+        push_variables(
+            &mut css,
+            ":root:not([data-theme=\"light\"])",
+            &dark_variables,
+            "  ",
+        );
+        css.push_str("}\n\n");
+        push_variables(&mut css, ":root[data-theme=\"dark\"]", &dark_variables, "");
+    }
+
+    css.push_str("\n.highlight {\n");
+    css.push_str("  color: var(--hl-fg);\n");
+    css.push_str("  background-color: var(--hl-bg);\n");
+    css.push_str("}\n");
+    css.push_str(LINE_CSS);
+
+    for classes in HIGHLIGHT_CLASS_NAMES {
+        let name = variable_name(classes);
+        let properties: Vec<_> = PROPERTIES
+            .iter()
+            .filter_map(|(property, suffix)| {
+                let variable = format!("{}{}", name, suffix);
+                let used = light_variables.contains_key(&variable)
+                    || dark_variables.contains_key(&variable);
+                used.then(|| format!("  {}: var({});\n", property, variable))
+            })
+            .collect();
+
+        if properties.is_empty() {
+            continue;
+        }
+
+        let selector: String = classes
+            .split_whitespace()
+            .map(|class| format!(".{}", class))
+            .collect();
+
+        css.push_str(&format!("\n.highlight {} {{\n", selector));
+        css.extend(properties);
+        css.push_str("}\n");
+    }
+
+    css
+}
+
+/// Rules for the lines of code blocks with line numbers or highlighted lines.
+const LINE_CSS: &str = r#"
+.highlight .code-line {
+  display: block;
+}
+
+.highlight .code-line.hl {
+  background-color: color-mix(in srgb, var(--hl-fg) 12%, transparent);
+}
+
+.highlight .code-line[data-line]::before {
+  content: attr(data-line);
+  display: inline-block;
+  min-width: 3ch;
+  margin-right: 1em;
+  text-align: right;
+  opacity: 0.5;
+  user-select: none;
+}
+"#;
+
+/// The CSS properties set by highlight rules and the suffixes of their variables.
+const PROPERTIES: [(&str, &str); 5] = [
+    ("color", ""),
+    ("background-color", "-bg"),
+    ("font-weight", "-weight"),
+    ("font-style", "-style"),
+    ("text-decoration", "-decoration"),
+];
+
+/// The CSS variables defined by a theme.
+fn variables(theme: &Theme) -> BTreeMap<String, String> {
+    let mut variables = BTreeMap::new();
+    variables.insert("--hl-fg".to_string(), theme.fg.into_hex());
+    variables.insert("--hl-bg".to_string(), theme.bg.into_hex());
+
+    for (name, classes) in HIGHLIGHT_NAMES.iter().zip(HIGHLIGHT_CLASS_NAMES) {
+        if let Some(style) = theme.get_style(name) {
+            let name = variable_name(classes);
+
+            for (suffix, value) in style_values(style) {
+                variables.insert(format!("{}{}", name, suffix), value);
+            }
+        }
+    }
+
+    variables
+}
+
+/// The values of the properties in [`PROPERTIES`] that a style sets.
+fn style_values(style: &Style) -> Vec<(&'static str, String)> {
+    let mut values = Vec::new();
+    let has = |modifier| style.modifiers.contains(&modifier);
+
+    if let Some(fg) = style.fg {
+        values.push(("", fg.into_hex()));
+    }
+
+    if let Some(bg) = style.bg {
+        values.push(("-bg", bg.into_hex()));
+    }
+
+    if has(Modifier::Bold) {
+        values.push(("-weight", "bold".to_string()));
+    }
+
+    if has(Modifier::Italic) {
+        values.push(("-style", "italic".to_string()));
+    }
+
+    let mut decorations = Vec::new();
+
+    if has(Modifier::Underlined) || style.underline.is_some() {
+        decorations.push("underline");
+    }
+
+    if has(Modifier::Strikethrough) {
+        decorations.push("line-through");
+    }
+
+    if !decorations.is_empty() {
+        values.push(("-decoration", decorations.join(" ")));
+    }
+
+    values
+}
+
+/// The prefix of the variables for a highlight class.
+fn variable_name(classes: &str) -> String {
+    format!(
+        "--hl-{}",
+        classes.split_whitespace().collect::<Vec<_>>().join("-")
+    )
+}
+
+/// Append a rule defining `variables` for `selector`.
+fn push_variables(
+    css: &mut String,
+    selector: &str,
+    variables: &BTreeMap<String, String>,
+    indent: &str,
+) {
+    css.push_str(&format!("{}{} {{\n", indent, selector));
+
+    for (name, value) in variables {
+        css.push_str(&format!("{}  {}: {};\n", indent, name, value));
+    }
+
+    css.push_str(&format!("{}}}\n", indent));
+}
+
+/// The data of a theme vendored by Inkjet.
+fn vendored_theme(name: &str) -> Option<&'static str> {
+    THEMES
+        .iter()
+        .find(|(theme, _)| *theme == name)
+        .map(|(_, data)| *data)
+}
+
+/// The themes vendored by Inkjet, by their names.
+pub const THEMES: &[(&str, &str)] = &[
+    ("acme", vendored::ACME),
+    ("adwaita-dark", vendored::ADWAITA_DARK),
+    ("amberwood", vendored::AMBERWOOD),
+    ("ao", vendored::AO),
+    ("ayu_dark", vendored::AYU_DARK),
+    ("ayu_light", vendored::AYU_LIGHT),
+    ("ayu_mirage", vendored::AYU_MIRAGE),
+    ("base16_default_dark", vendored::BASE16_DEFAULT_DARK),
+    ("base16_default_light", vendored::BASE16_DEFAULT_LIGHT),
+    ("base16_terminal", vendored::BASE16_TERMINAL),
+    ("base16_transparent", vendored::BASE16_TRANSPARENT),
+    ("bogster", vendored::BOGSTER),
+    ("bogster_light", vendored::BOGSTER_LIGHT),
+    ("boo_berry", vendored::BOO_BERRY),
+    ("catppuccin_mocha", vendored::CATPPUCCIN_MOCHA),
+    ("curzon", vendored::CURZON),
+    ("cyan_light", vendored::CYAN_LIGHT),
+    ("darcula", vendored::DARCULA),
+    ("dark_high_contrast", vendored::DARK_HIGH_CONTRAST),
+    ("dark_plus", vendored::DARK_PLUS),
+    ("doom_acario_dark", vendored::DOOM_ACARIO_DARK),
+    ("dracula", vendored::DRACULA),
+    ("dracula_at_night", vendored::DRACULA_AT_NIGHT),
+    ("emacs", vendored::EMACS),
+    ("everblush", vendored::EVERBLUSH),
+    ("everforest_dark", vendored::EVERFOREST_DARK),
+    ("everforest_light", vendored::EVERFOREST_LIGHT),
+    ("ferra", vendored::FERRA),
+    ("flatwhite", vendored::FLATWHITE),
+    ("fleet_dark", vendored::FLEET_DARK),
+    ("flexoki_light", vendored::FLEXOKI_LIGHT),
+    ("github_dark", vendored::GITHUB_DARK),
+    ("github_light", vendored::GITHUB_LIGHT),
+    ("gruber-darker", vendored::GRUBER_DARKER),
+    ("gruvbox", vendored::GRUVBOX),
+    ("heisenberg", vendored::HEISENBERG),
+    ("hex_steel", vendored::HEX_STEEL),
+    ("horizon-dark", vendored::HORIZON_DARK),
+    ("iceberg-dark", vendored::ICEBERG_DARK),
+    ("ingrid", vendored::INGRID),
+    ("iroaseta", vendored::IROASETA),
+    ("jellybeans", vendored::JELLYBEANS),
+    ("jetbrains_dark", vendored::JETBRAINS_DARK),
+    ("kanagawa", vendored::KANAGAWA),
+    ("kaolin-dark", vendored::KAOLIN_DARK),
+    ("material_deep_ocean", vendored::MATERIAL_DEEP_OCEAN),
+    ("meliora", vendored::MELIORA),
+    ("mellow", vendored::MELLOW),
+    ("merionette", vendored::MERIONETTE),
+    ("modus_operandi", vendored::MODUS_OPERANDI),
+    ("monokai", vendored::MONOKAI),
+    ("monokai_pro", vendored::MONOKAI_PRO),
+    ("monokai_pro_machine", vendored::MONOKAI_PRO_MACHINE),
+    ("monokai_pro_octagon", vendored::MONOKAI_PRO_OCTAGON),
+    ("monokai_pro_ristretto", vendored::MONOKAI_PRO_RISTRETTO),
+    ("monokai_pro_spectrum", vendored::MONOKAI_PRO_SPECTRUM),
+    ("monokai_soda", vendored::MONOKAI_SODA),
+    ("naysayer", vendored::NAYSAYER),
+    ("new_moon", vendored::NEW_MOON),
+    ("night_owl", vendored::NIGHT_OWL),
+    ("nightfox", vendored::NIGHTFOX),
+    ("noctis", vendored::NOCTIS),
+    ("noctis_bordo", vendored::NOCTIS_BORDO),
+    ("nord", vendored::NORD),
+    ("nord_light", vendored::NORD_LIGHT),
+    ("onedark", vendored::ONEDARK),
+    ("onedarker", vendored::ONEDARKER),
+    ("onelight", vendored::ONELIGHT),
+    ("papercolor-light", vendored::PAPERCOLOR_LIGHT),
+    ("penumbra-plus", vendored::PENUMBRA_PLUS),
+    ("poimandres", vendored::POIMANDRES),
+    ("pop-dark", vendored::POP_DARK),
+    ("rasmus", vendored::RASMUS),
+    ("rose_pine", vendored::ROSE_PINE),
+    ("serika-dark", vendored::SERIKA_DARK),
+    ("serika-light", vendored::SERIKA_LIGHT),
+    ("snazzy", vendored::SNAZZY),
+    ("solarized_dark", vendored::SOLARIZED_DARK),
+    ("solarized_light", vendored::SOLARIZED_LIGHT),
+    ("sonokai", vendored::SONOKAI),
+    ("spacebones_light", vendored::SPACEBONES_LIGHT),
+    ("starlight", vendored::STARLIGHT),
+    ("term16_dark", vendored::TERM16_DARK),
+    ("tokyonight", vendored::TOKYONIGHT),
+    ("ttox", vendored::TTOX),
+    ("varua", vendored::VARUA),
+    ("vim_dark_high_contrast", vendored::VIM_DARK_HIGH_CONTRAST),
+    ("voxed", vendored::VOXED),
+    ("yellowed", vendored::YELLOWED),
+    ("zed_onedark", vendored::ZED_ONEDARK),
+    ("zenburn", vendored::ZENBURN),
+];
+
+/// Error produced by [`load_theme`].
+#[derive(Debug, Error)]
+pub enum ThemeError {
+    /// No vendored theme with this name.
+    #[error("unknown theme `{0}`")]
+    Unknown(String),
+    /// Error while reading a theme file.
+    #[error("failed to read theme file {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// Theme that is not a valid Helix theme.
+    #[error("invalid theme `{name}`: {source}")]
+    Invalid {
+        name: String,
+        source: inkjet::ThemeError,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_vendored_themes_load() {
+        for (name, _) in THEMES {
+            if let Err(error) = load_theme(name) {
+                panic!("theme `{}` does not load: {}", name, error);
+            }
+        }
+    }
+
+    #[test]
+    fn load_theme_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("theme.toml");
+        std::fs::write(&path, vendored::GITHUB_LIGHT).unwrap();
+
+        assert!(load_theme(path.to_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn load_theme_reports_unknown_and_missing_themes() {
+        assert!(matches!(
+            load_theme("no_such_theme"),
+            Err(ThemeError::Unknown(name)) if name == "no_such_theme"
+        ));
+        assert!(matches!(
+            load_theme("missing/theme.toml"),
+            Err(ThemeError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn css_for_light_theme_only() {
+        let light = load_theme("github_light").unwrap();
+        let css = theme_css(&light, None);
+
+        assert!(css.starts_with(":root {\n"));
+        assert!(css.contains("  --hl-bg: #FFFFFF;\n"));
+        assert!(!css.contains("prefers-color-scheme"));
+        assert!(!css.contains("data-theme"));
+        assert!(css.contains(".highlight {\n  color: var(--hl-fg);\n"));
+        assert!(css.contains("\n.highlight .keyword {\n  color: var(--hl-keyword);\n"));
+    }
+
+    #[test]
+    fn css_for_light_and_dark_themes() {
+        let light = load_theme("github_light").unwrap();
+        let dark = load_theme("github_dark").unwrap();
+        let css = theme_css(&light, Some(&dark));
+
+        assert!(css.contains(
+            "@media (prefers-color-scheme: dark) {\n  :root:not([data-theme=\"light\"]) {\n"
+        ));
+        assert!(css.contains("\n:root[data-theme=\"dark\"] {\n"));
+
+        // Both themes define the text color, in their own blocks.
+        assert_eq!(css.matches("--hl-fg: ").count(), 3);
+    }
+
+    #[test]
+    fn dark_theme_resets_variables_it_does_not_set() {
+        let light = Theme::from_helix("\"keyword\" = \"#ff0000\"\n").unwrap();
+        let dark = Theme::from_helix("\"ui.text\" = \"#ffffff\"\n").unwrap();
+        let css = theme_css(&light, Some(&dark));
+
+        assert!(css.contains(":root {\n"));
+        assert!(css.contains("  --hl-keyword: #FF0000;\n"));
+        assert!(css.contains("    --hl-keyword: initial;\n"));
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::{ToolError, block_on, command_line, run_with_input};
+
+/// A command that reads a diagram from stdin and writes SVG to stdout, e.g.
+/// `dot -Tsvg` for Graphviz or `plantuml -tsvg -pipe` for PlantUML.
+#[derive(Debug, Clone, Hash, Deserialize, Serialize)]
+pub struct DiagramTool {
+    /// The command line.
+    pub command: Vec<String>,
+}
+
+impl DiagramTool {
+    /// Converts a diagram to SVG.
+    ///
+    /// Anything before the `<svg` element, like an XML declaration or a
+    /// doctype, is removed so that the SVG can be inlined into HTML. The tool
+    /// is killed if it does not finish within `timeout`.
+    pub async fn to_svg(&self, source: &str, timeout: Duration) -> Result<String, ToolError> {
+        let command = command_line(&self.command).await?;
+        let output = run_with_input(command, source.as_bytes(), timeout).await?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        let svg = match stdout.find("<svg") {
+            Some(start) if output.status.success() => &stdout[start..],
+            _ => {
+                return Err(ToolError::Failed {
+                    command: self.command.join(" "),
+                    output: String::from_utf8_lossy(&output.stderr).into(),
+                });
+            }
+        };
+
+        Ok(svg.trim_end().to_string())
+    }
+
+    /// Like [`Self::to_svg`], for use in synchronous code.
+    pub fn to_svg_blocking(&self, source: &str, timeout: Duration) -> Result<String, ToolError> {
+        block_on(self.to_svg(source, timeout))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool(script: &str) -> DiagramTool {
+        DiagramTool {
+            command: vec!["sh".into(), "-c".into(), script.into()],
+        }
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn svg_without_prolog() {
+        let tool = tool("cat >/dev/null; echo '<?xml version=\"1.0\"?>'; echo '<svg></svg>'");
+        assert_eq!(tool.to_svg_blocking("", TIMEOUT).unwrap(), "<svg></svg>");
+    }
+
+    #[test]
+    fn large_input_and_output() {
+        // The tool writes all of its output before it reads its input.
+        let tool = tool("echo '<svg>'; head -c 1000000 /dev/zero | tr '\\0' x; cat");
+        let input = "y".repeat(1_000_000);
+
+        let svg = tool.to_svg_blocking(&input, TIMEOUT).unwrap();
+        assert_eq!(svg.len(), "<svg>\n".len() + 2_000_000);
+    }
+
+    #[test]
+    fn timeout() {
+        let result = tool("sleep 10").to_svg_blocking("", Duration::from_millis(100));
+        assert!(matches!(result, Err(ToolError::Timeout { .. })));
+    }
+
+    #[test]
+    fn failure() {
+        let result = tool("echo bad >&2; exit 1").to_svg_blocking("", TIMEOUT);
+        assert!(matches!(
+            result,
+            Err(ToolError::Failed { output, .. }) if output == "bad\n"
+        ));
+    }
+
+    #[test]
+    fn empty_command() {
+        let result = DiagramTool { command: vec![] }.to_svg_blocking("", TIMEOUT);
+        assert!(matches!(result, Err(ToolError::EmptyCommand)));
+    }
+}
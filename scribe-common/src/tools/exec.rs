@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use tokio::fs;
+
+use super::{ToolError, block_on, command_line, run_with_input};
+
+/// A command that runs programs, e.g. `python3` or `rust-script`.
+#[derive(Debug, Clone, Hash, Deserialize, Serialize)]
+pub struct Interpreter {
+    /// The command line, to which the path of the program is appended.
+    pub command: Vec<String>,
+    /// The extension of the program file, which some interpreters require.
+    #[serde(default)]
+    pub extension: Option<String>,
+}
+
+/// The output of a program.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ExecOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// The exit code, if the program exited normally.
+    pub status: Option<i32>,
+}
+
+impl Interpreter {
+    /// Runs a program in an empty temporary directory.
+    ///
+    /// The program is killed if it does not finish within `timeout`. A program
+    /// that fails is not an error, its output and exit code are returned.
+    pub async fn run(&self, program: &str, timeout: Duration) -> Result<ExecOutput, ToolError> {
+        let mut command = command_line(&self.command).await?;
+
+        let temp_dir = TempDir::new()?;
+        let file_name = match &self.extension {
+            Some(extension) => format!("main.{}", extension),
+            None => "main".into(),
+        };
+        let file_path = temp_dir.path().join(file_name);
+
+        fs::write(&file_path, program).await?;
+
+        command.arg(&file_path).current_dir(temp_dir.path());
+        let output = run_with_input(command, &[], timeout).await?;
+
+        Ok(ExecOutput {
+            stdout: String::from_utf8_lossy(&output.stdout).into(),
+            stderr: String::from_utf8_lossy(&output.stderr).into(),
+            status: output.status.code(),
+        })
+    }
+
+    /// Like [`Self::run`], for use in synchronous code.
+    pub fn run_blocking(&self, program: &str, timeout: Duration) -> Result<ExecOutput, ToolError> {
+        block_on(self.run(program, timeout))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh() -> Interpreter {
+        Interpreter {
+            command: vec!["sh".into()],
+            extension: None,
+        }
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn output_and_status() {
+        let output = sh()
+            .run_blocking("echo out; echo err >&2; exit 3", TIMEOUT)
+            .unwrap();
+
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "err\n");
+        assert_eq!(output.status, Some(3));
+    }
+
+    #[test]
+    fn runs_in_empty_directory() {
+        let interpreter = Interpreter {
+            extension: Some("sh".into()),
+            ..sh()
+        };
+        let output = interpreter.run_blocking("ls", TIMEOUT).unwrap();
+
+        assert_eq!(output.stdout, "main.sh\n");
+    }
+
+    #[test]
+    fn timeout() {
+        let result = sh().run_blocking("sleep 10", Duration::from_millis(100));
+        assert!(matches!(result, Err(ToolError::Timeout { .. })));
+    }
+
+    #[test]
+    fn missing_tool() {
+        let interpreter = Interpreter {
+            command: vec!["scribe-missing-interpreter".into()],
+            extension: None,
+        };
+        let result = interpreter.run_blocking("", TIMEOUT);
+
+        assert!(matches!(result, Err(ToolError::MissingTool(_))));
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::{ToolError, block_on, command_line, run_with_input};
+
+/// A command that transforms a document, reading it from stdin and writing the
+/// result to stdout, e.g. `python3 filters/smallcaps.py`.
+#[derive(Debug, Clone, Hash, Deserialize, Serialize)]
+pub struct FilterCommand {
+    /// The command line.
+    pub command: Vec<String>,
+}
+
+impl FilterCommand {
+    /// Runs the command with `input` on stdin, returning its stdout.
+    ///
+    /// The command is killed if it does not finish within `timeout`.
+    pub async fn run(&self, input: &str, timeout: Duration) -> Result<String, ToolError> {
+        let command = command_line(&self.command).await?;
+        let output = run_with_input(command, input.as_bytes(), timeout).await?;
+
+        if !output.status.success() {
+            return Err(ToolError::Failed {
+                command: self.command.join(" "),
+                output: String::from_utf8_lossy(&output.stderr).into(),
+            });
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into())
+    }
+
+    /// Like [`Self::run`], for use in synchronous code.
+    pub fn run_blocking(&self, input: &str, timeout: Duration) -> Result<String, ToolError> {
+        block_on(self.run(input, timeout))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(script: &str) -> FilterCommand {
+        FilterCommand {
+            command: vec!["sh".into(), "-c".into(), script.into()],
+        }
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn output() {
+        let output = filter("tr a b").run_blocking("aaa", TIMEOUT).unwrap();
+        assert_eq!(output, "bbb");
+    }
+
+    #[test]
+    fn timeout() {
+        let result = filter("sleep 10").run_blocking("", Duration::from_millis(100));
+        assert!(matches!(result, Err(ToolError::Timeout { .. })));
+    }
+
+    #[test]
+    fn failure() {
+        let result = filter("echo bad >&2; exit 1").run_blocking("", TIMEOUT);
+        assert!(matches!(
+            result,
+            Err(ToolError::Failed { output, .. }) if output == "bad\n"
+        ));
+    }
+
+    #[test]
+    fn empty_command() {
+        let result = FilterCommand { command: vec![] }.run_blocking("", TIMEOUT);
+        assert!(matches!(result, Err(ToolError::EmptyCommand)));
+    }
+}
@@ -1,10 +1,13 @@
-use std::error::Error;
+use std::fmt::Display;
+use std::path::Path;
+use std::time::Duration;
 use tempfile::TempDir;
 use thiserror::Error;
 use tokio::fs;
-use tokio::io::AsyncWriteExt as _;
 use tokio::process::Command;
 
+use super::{ToolError, block_on, require_command, run_with_input};
+
 /// Converts latex to SVG.
 ///
 /// Requires the `latex` and `dvisvgm` tools to be on the `$PATH`.
@@ -13,29 +16,213 @@ pub async fn latex_to_svg(source: &str) -> Result<String, LatexError> {
     dvi_to_svg(&dvi).await
 }
 
-async fn latex_to_dvi(source: &str) -> Result<Vec<u8>, LatexError> {
-    if !is_command_available("latex").await {
-        return Err(LatexError::MissingTool("latex".into()));
+/// A LaTeX snippet taken from a note.
+///
+/// The snippet is wrapped into a document with the given preamble before it is
+/// compiled, and errors are reported relative to the lines of the note.
+#[derive(Debug, Clone, Copy)]
+pub struct LatexSnippet<'a> {
+    /// The preamble of the document, up to but excluding `\begin{document}`.
+    pub preamble: &'a str,
+    /// The body of the snippet.
+    pub body: &'a str,
+    /// The line of the note on which the body starts (1-based).
+    pub line: usize,
+}
+
+impl<'a> LatexSnippet<'a> {
+    pub fn new(preamble: &'a str, body: &'a str, line: usize) -> Self {
+        Self {
+            preamble,
+            body,
+            line,
+        }
+    }
+
+    /// The full LaTeX document that is passed to `latex`.
+    pub fn document(&self) -> String {
+        format!(
+            "{}\n\\begin{{document}}\n{}\n\\end{{document}}\n",
+            self.preamble.trim_end(),
+            self.body
+        )
+    }
+
+    /// Converts the snippet to SVG.
+    ///
+    /// Line numbers in [`LatexError::TexError`] refer to the note.
+    pub async fn to_svg(&self) -> Result<String, LatexError> {
+        latex_to_svg(&self.document())
+            .await
+            .map_err(|error| error.map_line(|line| self.note_line(line)))
+    }
+
+    /// Converts the snippet to an SVG that can be placed inline with text.
+    ///
+    /// The body is typeset in a `preview` environment so that the height and
+    /// depth of the resulting box are known.
+    pub async fn to_inline_svg(&self) -> Result<InlineSvg, LatexError> {
+        let dvi = latex_to_dvi(&self.inline_document())
+            .await
+            .map_err(|error| error.map_line(|line| self.note_line(line)))?;
+        let svg = dvi_to_inline_svg(&dvi).await?;
+
+        Ok(InlineSvg {
+            font_size: self.font_size(),
+            ..svg
+        })
+    }
+
+    /// Like [`Self::to_inline_svg`], for use in synchronous code.
+    ///
+    /// See [`block_on`] for how the conversion is run.
+    pub fn to_inline_svg_blocking(&self) -> Result<InlineSvg, LatexError> {
+        block_on(self.to_inline_svg())?
+    }
+
+    /// The document for [`Self::to_inline_svg`].
+    ///
+    /// Lines are kept in the same place as in [`Self::document`].
+    fn inline_document(&self) -> String {
+        format!(
+            "{}\n\\usepackage[active,tightpage]{{preview}}\\begin{{document}}\\begin{{preview}}\n{}\n\\end{{preview}}\\end{{document}}\n",
+            self.preamble.trim_end(),
+            self.body
+        )
+    }
+
+    /// The font size of the document in points, as set by an option of the
+    /// document class like `\documentclass[12pt]{article}`.
+    pub fn font_size(&self) -> f64 {
+        self.preamble
+            .split_once("\\documentclass")
+            .and_then(|(_, rest)| rest.trim_start().strip_prefix('['))
+            .and_then(|rest| Some(rest.split_once(']')?.0))
+            .and_then(|options| {
+                options
+                    .split(',')
+                    .find_map(|option| option.trim().strip_suffix("pt")?.trim().parse().ok())
+            })
+            .unwrap_or(DEFAULT_FONT_SIZE)
+    }
+
+    /// Maps a line of [`Self::document`] to the line of the note.
+    ///
+    /// Returns `None` for lines outside of the body.
+    fn note_line(&self, line: usize) -> Option<usize> {
+        // The body starts after the preamble and the `\begin{document}` line.
+        let start = self.preamble.trim_end().lines().count().max(1) + 2;
+        let end = start + self.body.lines().count().max(1);
+        (start..end)
+            .contains(&line)
+            .then(|| self.line + line - start)
     }
+}
+
+/// Font size of the standard LaTeX document classes, in points.
+const DEFAULT_FONT_SIZE: f64 = 10.0;
+
+/// An SVG rendered from an inline LaTeX snippet.
+///
+/// All dimensions are in TeX points, measured from the baseline.
+#[derive(Debug, Clone)]
+pub struct InlineSvg {
+    /// The SVG as produced by `dvisvgm`.
+    pub svg: String,
+    /// The width of the box.
+    pub width: f64,
+    /// The height of the box above the baseline.
+    pub height: f64,
+    /// The depth of the box below the baseline.
+    pub depth: f64,
+    /// The font size of the document, which the box is sized relative to in
+    /// [`Self::to_html`].
+    pub font_size: f64,
+}
+
+impl InlineSvg {
+    /// The SVG sized in `em` and aligned with the baseline of the text.
+    pub fn to_html(&self) -> String {
+        let style = format!(
+            "width: {:.3}em; height: {:.3}em; vertical-align: {:.3}em",
+            self.width / self.font_size,
+            (self.height + self.depth) / self.font_size,
+            -self.depth / self.font_size,
+        );
+
+        let Some(start) = self.svg.find("<svg") else {
+            return self.svg.clone();
+        };
+
+        let Some(end) = self.svg[start..].find('>').map(|end| start + end) else {
+            return self.svg.clone();
+        };
+
+        let mut tag = self.svg[start..end].to_string();
+        remove_attribute(&mut tag, "width");
+        remove_attribute(&mut tag, "height");
+
+        format!(
+            "{}{} style=\"{}\"{}",
+            &self.svg[..start],
+            tag,
+            style,
+            &self.svg[end..]
+        )
+    }
+}
+
+/// Removes an attribute from the source of an XML start tag.
+fn remove_attribute(tag: &mut String, name: &str) {
+    let pattern = format!(" {}=", name);
+
+    let Some(start) = tag.find(&pattern) else {
+        return;
+    };
+
+    let value = start + pattern.len();
+    let Some(quote) = tag[value..].chars().next() else {
+        return;
+    };
+
+    if let Some(end) = tag[value + 1..].find(quote) {
+        tag.replace_range(start..value + end + 2, "");
+    }
+}
+
+/// How long `latex` and `dvisvgm` may run before they are killed.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+async fn latex_to_dvi(source: &str) -> Result<Vec<u8>, LatexError> {
+    require_command("latex").await?;
 
     let temp_dir = TempDir::new()?;
     let file_path = temp_dir.path().join("input.tex");
 
     fs::write(&file_path, source).await?;
 
-    let output = Command::new("latex")
+    let mut command = Command::new("latex");
+    command
         .arg("-halt-on-error")
         .arg("-interaction=nonstopmode")
         .arg(file_path)
-        .output()
-        .await?;
+        .current_dir(temp_dir.path());
+
+    let output = run_with_input(command, &[], TIMEOUT).await?;
 
     if !output.status.success() {
+        if let Some(diagnostic) = read_tex_log(&temp_dir.path().join("input.log")).await {
+            return Err(LatexError::TexError(diagnostic));
+        }
+
+        // TeX reports errors on stdout, the log is only missing if it crashed.
+        let stdout = String::from_utf8_lossy(&output.stdout);
         let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(LatexError::CompileError {
+        return Err(ToolError::Failed {
             command: "latex".into(),
-            stderr: stderr.into(),
-        });
+            output: format!("{}{}", stdout, stderr),
+        }
+        .into());
     }
 
     let dvi_content = fs::read(&temp_dir.path().join("input.dvi")).await?;
@@ -43,61 +230,269 @@ async fn latex_to_dvi(source: &str) -> Result<Vec<u8>, LatexError> {
 }
 
 async fn dvi_to_svg(dvi: &[u8]) -> Result<String, LatexError> {
-    if !is_command_available("dvisvgm").await {
-        return Err(LatexError::MissingTool("dvisvgm".into()));
-    }
+    let (svg, _) = run_dvisvgm(dvi, &["--bbox=papersize", "--zoom=1.5"]).await?;
+    Ok(svg)
+}
+
+async fn dvi_to_inline_svg(dvi: &[u8]) -> Result<InlineSvg, LatexError> {
+    let (svg, log) = run_dvisvgm(dvi, &["--bbox=preview"]).await?;
+
+    let Some((width, height, depth)) = parse_dvisvgm_extents(&log) else {
+        return Err(ToolError::Failed {
+            command: "dvisvgm".into(),
+            output: format!("could not determine the size of the graphic:\n{}", log),
+        }
+        .into());
+    };
+
+    Ok(InlineSvg {
+        svg,
+        width,
+        height,
+        depth,
+        font_size: DEFAULT_FONT_SIZE,
+    })
+}
 
-    let mut child = Command::new("dvisvgm")
+/// Runs `dvisvgm` and returns the SVG together with its log output.
+async fn run_dvisvgm(dvi: &[u8], args: &[&str]) -> Result<(String, String), LatexError> {
+    require_command("dvisvgm").await?;
+
+    let mut command = Command::new("dvisvgm");
+    command
         .arg("--exact")
         .arg("--clipjoin")
         .arg("--font-format=woff")
-        .arg("--bbox=papersize")
-        .arg("--zoom=1.5")
+        .args(args)
         .arg("--stdin")
-        .arg("--stdout")
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped())
-        .spawn()?;
-
-    let mut stdin = child.stdin.take().unwrap();
-    stdin.write_all(dvi).await?;
-    drop(stdin);
+        .arg("--stdout");
 
-    let output = child.wait_with_output().await?;
+    let output = run_with_input(command, dvi, TIMEOUT).await?;
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(LatexError::CompileError {
+        return Err(ToolError::Failed {
             command: "dvisvgm".into(),
-            stderr: stderr.into(),
-        });
+            output: stderr,
+        }
+        .into());
     }
 
     let svg = String::from_utf8_lossy(&output.stdout).into();
-    Ok(svg)
+    Ok((svg, stderr))
+}
+
+/// Extracts the width, height and depth in points from the `dvisvgm` log.
+///
+/// With `--bbox=preview`, `dvisvgm` reports the extents of the box as set by
+/// the preview package, e.g. `width=22.29pt, height=6.94pt, depth=1.93pt`.
+/// Otherwise only the graphic size is known and the depth is assumed to be zero.
+fn parse_dvisvgm_extents(log: &str) -> Option<(f64, f64, f64)> {
+    let points = |value: &str| -> Option<f64> {
+        let (value, _) = value.trim_start().split_once("pt")?;
+        value.parse().ok()
+    };
+
+    let dimension = |line: &str, key: &str| points(line.split_once(key)?.1);
+
+    let preview = log.lines().find_map(|line| {
+        let width = dimension(line, "width=")?;
+        let height = dimension(line, "height=")?;
+        let depth = dimension(line, "depth=")?;
+        Some((width, height, depth))
+    });
+
+    preview.or_else(|| {
+        log.lines().find_map(|line| {
+            let (width, height) = line.split_once("graphic size:")?.1.split_once(" x ")?;
+            Some((points(width)?, points(height)?, 0.0))
+        })
+    })
+}
+
+async fn read_tex_log(path: &Path) -> Option<TexDiagnostic> {
+    let log = fs::read(path).await.ok()?;
+    parse_tex_log(&String::from_utf8_lossy(&log))
+}
+
+/// Extracts the first error from a TeX log.
+///
+/// TeX reports errors as a line starting with `!`, followed by some help text
+/// and a line of the form `l.<line> <context>`. The rest of the offending line
+/// is printed on the next line.
+fn parse_tex_log(log: &str) -> Option<TexDiagnostic> {
+    let mut lines = log.lines().skip_while(|line| !line.starts_with("! "));
+    let message = lines.next()?.trim_start_matches("! ").trim().to_string();
+
+    let mut diagnostic = TexDiagnostic {
+        message,
+        line: None,
+        context: String::new(),
+    };
+
+    while let Some(line) = lines.next() {
+        if line.starts_with("! ") {
+            break;
+        }
+
+        let Some((number, before)) = line
+            .strip_prefix("l.")
+            .and_then(|rest| rest.split_once(' ').or(Some((rest, ""))))
+        else {
+            continue;
+        };
+
+        let Ok(number) = number.parse() else {
+            continue;
+        };
+
+        let after = lines.next().unwrap_or_default().trim();
+        diagnostic.line = Some(number);
+        diagnostic.context = format!("{}{}", before, after).trim().to_string();
+        break;
+    }
+
+    Some(diagnostic)
+}
+
+/// An error reported by TeX, extracted from its log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TexDiagnostic {
+    /// The error message.
+    pub message: String,
+    /// The line of the source at which the error occurred, if known.
+    pub line: Option<usize>,
+    /// The source text around the error, as printed by TeX.
+    pub context: String,
 }
 
-async fn is_command_available(command: &str) -> bool {
-    Command::new("which")
-        .arg(command)
-        .output()
-        .await
-        .map(|output| output.status.success())
-        .unwrap_or(false)
+impl Display for TexDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+
+        match (self.line, self.context.is_empty()) {
+            (Some(line), true) => write!(f, " (line {})", line),
+            (Some(line), false) => write!(f, " (line {}, at `{}`)", line, self.context),
+            (None, false) => write!(f, " (at `{}`)", self.context),
+            (None, true) => Ok(()),
+        }
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum LatexError {
-    #[error("The CLI tool `{0}` is missing in the $PATH.")]
-    MissingTool(String),
-    #[error("Failed to run `{command}`:\n{stderr}")]
-    CompileError { command: String, stderr: String },
-    #[error("Error while compiling latex.")]
-    Other(#[source] Box<dyn Error>),
+    #[error("LaTeX error: {0}")]
+    TexError(TexDiagnostic),
+    /// `latex` or `dvisvgm` could not be run or failed without a TeX error.
+    #[error(transparent)]
+    Tool(#[from] ToolError),
+}
+
+impl LatexError {
+    /// Maps the line reported by a [`LatexError::TexError`].
+    ///
+    /// The line is dropped when `f` returns `None`.
+    pub fn map_line(self, f: impl FnOnce(usize) -> Option<usize>) -> Self {
+        match self {
+            Self::TexError(mut diagnostic) => {
+                diagnostic.line = diagnostic.line.and_then(f);
+                Self::TexError(diagnostic)
+            }
+            error => error,
+        }
+    }
 }
 
 impl From<std::io::Error> for LatexError {
     fn from(value: std::io::Error) -> Self {
-        Self::Other(value.into())
+        Self::Tool(value.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_tex_log_error_with_line() {
+        let log = "(./input.tex\n\
+! Undefined control sequence.\n\
+l.5 $\\frac{1}{2} + \\foo\n\
+                        {x}$\n\
+Here is how much of TeX's memory you used:\n";
+
+        let diagnostic = parse_tex_log(log).unwrap();
+        assert_eq!(diagnostic.message, "Undefined control sequence.");
+        assert_eq!(diagnostic.line, Some(5));
+        assert_eq!(diagnostic.context, "$\\frac{1}{2} + \\foo{x}$");
+    }
+
+    #[test]
+    fn parse_tex_log_error_without_line() {
+        let log = "! Emergency stop.\n<*> input.tex\n\n! ==> Fatal error occurred.\n";
+
+        let diagnostic = parse_tex_log(log).unwrap();
+        assert_eq!(diagnostic.message, "Emergency stop.");
+        assert_eq!(diagnostic.line, None);
+        assert_eq!(diagnostic.context, "");
+    }
+
+    #[test]
+    fn parse_tex_log_first_error() {
+        let log = "! Missing $ inserted.\nl.3 x^\n       2\n! Extra }, or forgotten $.\nl.4 }\n";
+
+        let diagnostic = parse_tex_log(log).unwrap();
+        assert_eq!(diagnostic.message, "Missing $ inserted.");
+        assert_eq!(diagnostic.line, Some(3));
+        assert_eq!(diagnostic.context, "x^2");
+    }
+
+    #[test]
+    fn parse_tex_log_without_error() {
+        assert_eq!(
+            parse_tex_log("This is pdfTeX\nOutput written on input.dvi\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn font_size_from_document_class() {
+        let font_size = |preamble| LatexSnippet::new(preamble, "", 1).font_size();
+
+        assert_eq!(font_size("\\documentclass{article}"), 10.0);
+        assert_eq!(font_size("\\documentclass[12pt]{article}"), 12.0);
+        assert_eq!(font_size("\\documentclass[a4paper, 11pt]{report}"), 11.0);
+        assert_eq!(font_size("\\documentclass[draft]{article}"), 10.0);
+    }
+
+    #[test]
+    fn inline_svg_sized_in_em() {
+        let svg = InlineSvg {
+            svg: "<svg width='24pt' height='12pt'></svg>".into(),
+            width: 24.0,
+            height: 9.0,
+            depth: 3.0,
+            font_size: 12.0,
+        };
+
+        assert_eq!(
+            svg.to_html(),
+            "<svg style=\"width: 2.000em; height: 1.000em; vertical-align: -0.250em\"></svg>"
+        );
+    }
+
+    #[test]
+    fn note_line_maps_body_lines() {
+        let snippet = LatexSnippet::new(
+            "\\documentclass{article}\n\\usepackage{amsmath}",
+            "a\nb",
+            10,
+        );
+
+        // Two preamble lines and `\begin{document}` come before the body.
+        assert_eq!(snippet.note_line(3), None);
+        assert_eq!(snippet.note_line(4), Some(10));
+        assert_eq!(snippet.note_line(5), Some(11));
+        assert_eq!(snippet.note_line(6), None);
     }
 }
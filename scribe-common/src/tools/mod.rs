@@ -1 +1,130 @@
+use std::error::Error;
+use std::future::Future;
+use std::process::{Output, Stdio};
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::io::AsyncWriteExt as _;
+use tokio::process::Command;
+
+pub mod diagram;
+pub mod exec;
+pub mod filter;
 pub mod latex;
+pub mod svg;
+
+/// Runs a future to completion, for use in synchronous code.
+///
+/// The future runs on a separate thread with its own runtime, so that this
+/// can be called both from within and outside of a Tokio runtime.
+pub fn block_on<F>(future: F) -> std::io::Result<F::Output>
+where
+    F: Future + Send,
+    F::Output: Send,
+{
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?;
+                Ok(runtime.block_on(future))
+            })
+            .join()
+            .unwrap()
+    })
+}
+
+/// Whether a command is on the `$PATH`.
+async fn is_command_available(command: &str) -> bool {
+    Command::new("which")
+        .arg(command)
+        .output()
+        .await
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// Checks that a command is on the `$PATH`.
+pub(crate) async fn require_command(command: &str) -> Result<(), ToolError> {
+    match is_command_available(command).await {
+        true => Ok(()),
+        false => Err(ToolError::MissingTool(command.into())),
+    }
+}
+
+/// The [`Command`] for a configured command line, like `["dot", "-Tsvg"]`.
+pub(crate) async fn command_line(command_line: &[String]) -> Result<Command, ToolError> {
+    let Some((program, args)) = command_line.split_first() else {
+        return Err(ToolError::EmptyCommand);
+    };
+
+    require_command(program).await?;
+
+    let mut command = Command::new(program);
+    command.args(args);
+    Ok(command)
+}
+
+/// Runs a command with `input` on stdin and collects its output.
+///
+/// Stdin is written concurrently, so that a command writing output before it
+/// has read all input cannot block on a full pipe. The command is killed if
+/// it does not finish within `timeout`. A command that fails is not an error,
+/// callers decide what its exit status means.
+pub(crate) async fn run_with_input(
+    mut command: Command,
+    input: &[u8],
+    timeout: Duration,
+) -> Result<Output, ToolError> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = tokio::spawn(async move {
+        let result = stdin.write_all(&input).await;
+        drop(stdin);
+        result
+    });
+
+    let Ok(output) = tokio::time::timeout(timeout, child.wait_with_output()).await else {
+        writer.abort();
+        return Err(ToolError::Timeout {
+            command: command.as_std().get_program().to_string_lossy().into(),
+            timeout,
+        });
+    };
+
+    // A command that exits without reading all input closes the pipe, which
+    // is not an error as long as it succeeds.
+    let _ = writer.await;
+
+    Ok(output?)
+}
+
+/// Error while running an external tool.
+#[derive(Debug, Error)]
+pub enum ToolError {
+    #[error("The CLI tool `{0}` is missing in the $PATH.")]
+    MissingTool(String),
+    #[error("The tool has an empty command.")]
+    EmptyCommand,
+    #[error("`{command}` did not finish within {} seconds.", timeout.as_secs_f64())]
+    Timeout { command: String, timeout: Duration },
+    /// A tool failed, with its output, or a description of what went wrong.
+    #[error("Failed to run `{command}`:\n{output}")]
+    Failed { command: String, output: String },
+    #[error("Error while running tool.")]
+    Other(#[source] Box<dyn Error + Send + Sync>),
+}
+
+impl From<std::io::Error> for ToolError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Other(value.into())
+    }
+}
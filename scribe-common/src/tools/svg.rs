@@ -0,0 +1,531 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+/// Post-processes SVGs generated by `dvisvgm` for embedding into HTML pages.
+///
+/// A processor is meant to be used for all figures of a single page:
+///
+/// - Black fills and strokes are replaced by `currentColor`, so that figures
+///   follow the text color of the page (e.g. in dark mode).
+/// - Ids and class names are prefixed with a per-figure namespace, so that
+///   several SVGs can be inlined into one page without collisions.
+/// - Embedded fonts are renamed after their contents. `dvisvgm` only embeds
+///   the glyphs used by a figure, and the `@font-face` rules of inline SVGs
+///   apply to the whole page, so figures would otherwise pick up each other's
+///   subsets of a font with the same name.
+/// - Optionally, `@font-face` rules that an earlier figure already emitted are
+///   removed. Identical subsets get identical names, so the first rule on the
+///   page serves all figures that use the subset.
+#[derive(Debug, Clone)]
+pub struct SvgProcessor {
+    prefix: String,
+    count: usize,
+    current_color: bool,
+    dedup_fonts: bool,
+    fonts: HashSet<String>,
+}
+
+impl SvgProcessor {
+    /// Creates a processor that namespaces ids with the given prefix.
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            count: 0,
+            current_color: true,
+            dedup_fonts: false,
+            fonts: HashSet::new(),
+        }
+    }
+
+    /// Whether to replace black with `currentColor`. Enabled by default.
+    pub fn current_color(mut self, enabled: bool) -> Self {
+        self.current_color = enabled;
+        self
+    }
+
+    /// Whether to remove fonts that were already emitted for the page. Disabled
+    /// by default.
+    pub fn dedup_fonts(mut self, enabled: bool) -> Self {
+        self.dedup_fonts = enabled;
+        self
+    }
+
+    /// Processes the next SVG on the page.
+    pub fn process(&mut self, svg: &str) -> String {
+        self.process_with(svg, self.dedup_fonts)
+    }
+
+    fn process_with(&mut self, svg: &str, dedup_fonts: bool) -> String {
+        self.count += 1;
+        let namespace = format!("{}{}", self.prefix, self.count);
+
+        let mut classes = HashSet::new();
+
+        let svg = map_attributes(svg, |name, value| {
+            let value = prefix_urls(value, &namespace);
+
+            match name {
+                "id" => format!("{}-{}", namespace, value),
+                "href" | "xlink:href" if value.starts_with('#') => {
+                    format!("#{}-{}", namespace, &value[1..])
+                }
+                "class" => value
+                    .split_whitespace()
+                    .map(|class| {
+                        classes.insert(class.to_string());
+                        format!("{}-{}", namespace, class)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                "fill" | "stroke" | "color" if self.current_color && is_black(&value) => {
+                    "currentColor".into()
+                }
+                "style" if self.current_color => replace_black_css(&value),
+                _ => value,
+            }
+        });
+
+        let mut svg = map_styles(&svg, |css| {
+            let css = prefix_urls(css, &namespace);
+            let css = prefix_classes(&css, &classes, &namespace);
+            let css = rename_fonts(&css, &mut self.fonts, dedup_fonts);
+
+            match self.current_color {
+                true => replace_black_css(&css),
+                false => css,
+            }
+        });
+
+        if self.current_color {
+            set_root_fill(&mut svg);
+        }
+
+        svg
+    }
+}
+
+/// An [`SvgProcessor`] shared by the filters of a page.
+///
+/// The figures of all filters, e.g. math rendered by LaTeX and diagrams, are
+/// numbered in one sequence so that their namespaces do not collide, and fonts
+/// are deduplicated across all of them.
+#[derive(Debug, Clone)]
+pub struct SharedSvgProcessor {
+    processor: Arc<Mutex<SvgProcessor>>,
+    dedup_fonts: bool,
+}
+
+impl SharedSvgProcessor {
+    pub fn new(processor: SvgProcessor) -> Self {
+        Self {
+            dedup_fonts: processor.dedup_fonts,
+            processor: Arc::new(Mutex::new(processor)),
+        }
+    }
+
+    /// Whether to remove fonts that were already emitted for the page, for the
+    /// SVGs processed through this handle. See [`SvgProcessor::dedup_fonts`].
+    pub fn dedup_fonts(mut self, enabled: bool) -> Self {
+        self.dedup_fonts = enabled;
+        self
+    }
+
+    /// Processes the next SVG on the page, see [`SvgProcessor::process`].
+    pub fn process(&self, svg: &str) -> String {
+        self.processor
+            .lock()
+            .unwrap()
+            .process_with(svg, self.dedup_fonts)
+    }
+}
+
+/// Whether a color value denotes black.
+fn is_black(value: &str) -> bool {
+    matches!(
+        value.trim().to_ascii_lowercase().as_str(),
+        "#000" | "#000000" | "black" | "rgb(0,0,0)" | "rgb(0, 0, 0)"
+    )
+}
+
+/// Replaces black fills, strokes and colors in CSS declarations.
+fn replace_black_css(css: &str) -> String {
+    let mut css = css.to_string();
+
+    for property in ["fill", "stroke", "color"] {
+        for value in ["#000000", "#000", "black", "rgb(0,0,0)"] {
+            for separator in [":", ": "] {
+                let from = format!("{}{}{}", property, separator, value);
+                let to = format!("{}{}currentColor", property, separator);
+                css = css.replace(&from, &to);
+            }
+        }
+    }
+
+    css
+}
+
+/// Sets the default fill of the root element to `currentColor`.
+///
+/// Shapes without an explicit fill are black by default.
+fn set_root_fill(svg: &mut String) {
+    let Some(start) = svg.find("<svg") else {
+        return;
+    };
+
+    let Some(end) = svg[start..].find('>').map(|end| start + end) else {
+        return;
+    };
+
+    if !svg[start..end].contains(" fill=") {
+        svg.insert_str(start + "<svg".len(), " fill='currentColor'");
+    }
+}
+
+/// Prefixes references of the form `url(#id)`.
+fn prefix_urls(value: &str, namespace: &str) -> String {
+    value.replace("url(#", &format!("url(#{}-", namespace))
+}
+
+/// Prefixes class selectors for the given classes.
+fn prefix_classes(css: &str, classes: &HashSet<String>, namespace: &str) -> String {
+    let mut output = String::with_capacity(css.len());
+    let mut rest = css;
+
+    while let Some(start) = rest.find('.') {
+        output.push_str(&rest[..=start]);
+        rest = &rest[start + 1..];
+
+        let end = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'))
+            .unwrap_or(rest.len());
+
+        if classes.contains(&rest[..end]) {
+            output.push_str(namespace);
+            output.push('-');
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// Renames the fonts declared in a stylesheet after their contents.
+///
+/// The renamed families are added to `emitted`. With `dedup`, rules for
+/// families that were already emitted are removed.
+fn rename_fonts(css: &str, emitted: &mut HashSet<String>, dedup: bool) -> String {
+    let mut renames = HashMap::new();
+    let mut output = String::with_capacity(css.len());
+    let mut rest = css;
+
+    while let Some(start) = rest.find("@font-face") {
+        let Some(end) = rest[start..].find('}').map(|end| start + end + 1) else {
+            break;
+        };
+
+        output.push_str(&rest[..start]);
+        let rule = &rest[start..end];
+        rest = &rest[end..];
+
+        let Some(family) = font_family(rule) else {
+            output.push_str(rule);
+            continue;
+        };
+
+        let mut hasher = DefaultHasher::new();
+        rule.hash(&mut hasher);
+
+        let renamed = format!("{}-{:016x}", family, hasher.finish());
+        if emitted.insert(renamed.clone()) || !dedup {
+            output.push_str(&rename_font_family(rule, family, &renamed));
+        }
+        renames.insert(family.to_string(), renamed);
+    }
+
+    output.push_str(rest);
+
+    renames
+        .iter()
+        .fold(output, |css, (from, to)| rename_font_family(&css, from, to))
+}
+
+/// The font family declared in a `@font-face` rule.
+fn font_family(rule: &str) -> Option<&str> {
+    let (_, rest) = rule.split_once("font-family:")?;
+    let end = rest.find([';', '}'])?;
+    Some(rest[..end].trim().trim_matches(['\'', '"']))
+}
+
+/// Renames a font family in `font-family` declarations.
+fn rename_font_family(css: &str, from: &str, to: &str) -> String {
+    let mut css = css.to_string();
+
+    for separator in [":", ": "] {
+        for terminator in [";", "}"] {
+            let pattern = format!("font-family{}{}{}", separator, from, terminator);
+            let replacement = format!("font-family{}{}{}", separator, to, terminator);
+            css = css.replace(&pattern, &replacement);
+        }
+    }
+
+    css
+}
+
+/// Applies `f` to the contents of all `<style>` elements.
+///
+/// A `CDATA` section around the stylesheet is preserved.
+fn map_styles(svg: &str, mut f: impl FnMut(&str) -> String) -> String {
+    let mut output = String::with_capacity(svg.len());
+    let mut rest = svg;
+
+    while let Some(start) = rest.find("<style") {
+        let Some(open) = rest[start..].find('>').map(|open| start + open + 1) else {
+            break;
+        };
+
+        let Some(close) = rest[open..].find("</style>").map(|close| open + close) else {
+            break;
+        };
+
+        output.push_str(&rest[..open]);
+
+        let css = &rest[open..close];
+        match css
+            .split_once("<![CDATA[")
+            .and_then(|(before, css)| Some((before, css.rsplit_once("]]>")?)))
+        {
+            Some((before, (css, after))) => {
+                output.push_str(before);
+                output.push_str("<![CDATA[");
+                output.push_str(&f(css));
+                output.push_str("]]>");
+                output.push_str(after);
+            }
+            None => output.push_str(&f(css)),
+        }
+
+        rest = &rest[close..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// Applies `f` to the attributes of all start tags.
+///
+/// `f` receives the name and value of an attribute and returns its new value.
+/// Comments, `CDATA` sections and declarations are left untouched.
+fn map_attributes(svg: &str, mut f: impl FnMut(&str, &str) -> String) -> String {
+    let mut output = String::with_capacity(svg.len());
+    let mut rest = svg;
+
+    while let Some(start) = rest.find('<') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let verbatim = if rest.starts_with("<!--") {
+            rest.find("-->").map(|end| end + 3)
+        } else if rest.starts_with("<![CDATA[") {
+            rest.find("]]>").map(|end| end + 3)
+        } else if rest.starts_with("<?") || rest.starts_with("<!") || rest.starts_with("</") {
+            rest.find('>').map(|end| end + 1)
+        } else {
+            None
+        };
+
+        if let Some(end) = verbatim {
+            output.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+
+        // Copy the tag name.
+        let name_end = rest[1..]
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .map(|end| end + 1)
+            .unwrap_or(rest.len());
+        output.push_str(&rest[..name_end]);
+        rest = &rest[name_end..];
+
+        // Copy the attributes, up to the end of the tag.
+        loop {
+            let trimmed = rest.trim_start();
+            output.push_str(&rest[..rest.len() - trimmed.len()]);
+            rest = trimmed;
+
+            if rest.is_empty() || rest.starts_with('>') || rest.starts_with("/>") {
+                break;
+            }
+
+            let Some(eq) = rest.find('=') else {
+                break;
+            };
+
+            let name = rest[..eq].trim();
+            let Some(quote) = rest[eq + 1..].chars().next().filter(|c| *c == '\'' || *c == '"')
+            else {
+                break;
+            };
+
+            let value_start = eq + 2;
+            let Some(value_end) = rest[value_start..].find(quote).map(|end| value_start + end)
+            else {
+                break;
+            };
+
+            let value = f(name, &rest[value_start..value_end]);
+
+            output.push_str(name);
+            output.push('=');
+            output.push(quote);
+            output.push_str(&value);
+            output.push(quote);
+
+            rest = &rest[value_end + 1..];
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SVG: &str = "<svg><style>@font-face{font-family:cmr10;src:url(data:font/woff;base64,AAAA)}\ntext.f0{font-family:cmr10}</style><text id='t' class='f0' fill='#000'>x</text></svg>";
+
+    #[test]
+    fn shared_processor_numbers_figures_in_one_sequence() {
+        let svg = SharedSvgProcessor::new(SvgProcessor::new("svg"));
+        let math = svg.clone();
+
+        assert!(math.process(SVG).contains("id='svg1-t'"));
+        assert!(svg.process(SVG).contains("id='svg2-t'"));
+    }
+
+    #[test]
+    fn replaces_black_with_current_color() {
+        let mut svg = SvgProcessor::new("svg");
+        let output = svg.process(
+            "<svg><style>path{stroke:#000000}</style><path fill='#000' stroke='red'/><rect style='fill: black'/></svg>",
+        );
+
+        assert_eq!(
+            output,
+            "<svg fill='currentColor'><style>path{stroke:currentColor}</style><path fill='currentColor' stroke='red'/><rect style='fill: currentColor'/></svg>"
+        );
+    }
+
+    #[test]
+    fn keeps_black_without_current_color() {
+        let mut svg = SvgProcessor::new("svg").current_color(false);
+        let input = "<svg><path fill='#000'/></svg>";
+
+        assert_eq!(svg.process(input), input);
+    }
+
+    #[test]
+    fn keeps_explicit_root_fill() {
+        let mut svg = SvgProcessor::new("svg");
+
+        assert_eq!(
+            svg.process("<svg fill='red'><path/></svg>"),
+            "<svg fill='red'><path/></svg>"
+        );
+    }
+
+    #[test]
+    fn namespaces_ids_and_references() {
+        let mut svg = SvgProcessor::new("svg");
+        let output = svg.process(
+            "<svg><defs><path id='g0-1'/><clipPath id=\"c\"/></defs><use xlink:href='#g0-1' clip-path='url(#c)'/><use href='#g0-1'/><a href='https://example.com'/></svg>",
+        );
+
+        assert_eq!(
+            output,
+            "<svg fill='currentColor'><defs><path id='svg1-g0-1'/><clipPath id=\"svg1-c\"/></defs><use xlink:href='#svg1-g0-1' clip-path='url(#svg1-c)'/><use href='#svg1-g0-1'/><a href='https://example.com'/></svg>"
+        );
+    }
+
+    #[test]
+    fn namespaces_urls_and_classes_in_styles() {
+        let mut svg = SvgProcessor::new("svg");
+        let output = svg.process(
+            "<svg><style><![CDATA[text.f0{clip-path:url(#c)} .other{}]]></style><text class='f0'/></svg>",
+        );
+
+        assert_eq!(
+            output,
+            "<svg fill='currentColor'><style><![CDATA[text.svg1-f0{clip-path:url(#svg1-c)} .other{}]]></style><text class='svg1-f0'/></svg>"
+        );
+    }
+
+    #[test]
+    fn leaves_comments_alone() {
+        let mut svg = SvgProcessor::new("svg").current_color(false);
+        let input = "<?xml version='1.0'?><!-- id='x' --><svg><path id='p'/></svg>";
+
+        assert_eq!(
+            svg.process(input),
+            "<?xml version='1.0'?><!-- id='x' --><svg><path id='svg1-p'/></svg>"
+        );
+    }
+
+    #[test]
+    fn renames_fonts_after_their_contents() {
+        let subset = |glyphs: &str| {
+            format!(
+                "<svg><style>@font-face{{font-family:cmr10;src:url(data:font/woff;base64,{})}}\ntext.f0{{font-family:cmr10;}}</style></svg>",
+                glyphs
+            )
+        };
+
+        let family = |svg: &str| {
+            let (_, rest) = svg.split_once("font-family:").unwrap();
+            rest[..rest.find([';', '}']).unwrap()].to_string()
+        };
+
+        let mut svg = SvgProcessor::new("svg");
+        let first = svg.process(&subset("AAAA"));
+        let second = svg.process(&subset("BBBB"));
+        let third = svg.process(&subset("AAAA"));
+
+        assert!(family(&first).starts_with("cmr10-"));
+        assert_ne!(family(&first), family(&second));
+        assert_eq!(family(&first), family(&third));
+
+        // The text of the figure uses the renamed font.
+        assert_eq!(first.matches(&family(&first)).count(), 2);
+    }
+
+    #[test]
+    fn dedups_fonts_shared_by_figures() {
+        let subset = |glyphs: &str| {
+            format!(
+                "<svg><style>@font-face{{font-family:cmr10;src:url(data:font/woff;base64,{})}}\ntext.f0{{font-family:cmr10;}}</style></svg>",
+                glyphs
+            )
+        };
+
+        let svg = SharedSvgProcessor::new(SvgProcessor::new("svg").dedup_fonts(true));
+        let first = svg.process(&subset("AAAA"));
+        let second = svg.process(&subset("AAAA"));
+        let third = svg.process(&subset("BBBB"));
+
+        assert!(first.contains("@font-face"));
+        assert!(!second.contains("@font-face"));
+        assert!(third.contains("@font-face"));
+
+        // The second figure uses the font emitted by the first.
+        let (_, rest) = first.split_once("font-family:").unwrap();
+        let family = &rest[..rest.find([';', '}']).unwrap()];
+        assert!(second.contains(&format!("font-family:{};", family)));
+
+        // Without deduplication, every figure keeps its fonts.
+        let svg = svg.dedup_fonts(false);
+        assert!(svg.process(&subset("AAAA")).contains("@font-face"));
+    }
+}
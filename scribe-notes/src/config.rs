@@ -1,26 +1,727 @@
-use anyhow::Result;
-use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use inkjet::Language;
+use scribe_common::djot::{
+    DEFAULT_OUTPUT_LANGUAGE, ErrorStyle, MacroPersistence, MathFallback, PipelineConfig,
+    check_macros, load_theme, parse_macros, theme_css,
+};
+use scribe_common::tools::{diagram::DiagramTool, exec::Interpreter, filter::FilterCommand};
+use serde::{Deserialize, Serialize};
+use tera::Value;
+use thiserror::Error;
 
 /// Notes configuration.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub math: MathConfig,
+    #[serde(default)]
+    pub highlight: HighlightConfig,
+    #[serde(default)]
+    pub exec: ExecConfig,
+    #[serde(default)]
+    pub diagrams: DiagramConfig,
+    #[serde(default)]
+    pub errors: ErrorConfig,
+    #[serde(default)]
+    pub filters: FiltersConfig,
+    #[serde(default)]
+    pub scripts: ScriptsConfig,
+    /// Schema of the custom fields of the frontmatter, if they are checked.
+    #[serde(default)]
+    pub schema: Option<SchemaConfig>,
+    /// Default frontmatter of the notes whose paths match a glob, e.g.
+    /// `"notes/physics/*" = { tags = ["physics"] }`.
+    #[serde(default)]
+    pub defaults: BTreeMap<String, Value>,
+    /// The content of the config file, to point at it in errors.
+    #[serde(skip)]
+    pub source: String,
+}
+
+/// Configuration of the filters applied to notes.
+///
+/// The built-in filters are `scripts`, `headings`, `equations`, `math`,
+/// `include`, `exec`, `diagrams` and `highlight`, in this order by default,
+/// followed by the external filters in the order of their names. Of these,
+/// `headings` has options: the `offset` by which headings are demoted, 1 by
+/// default.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FiltersConfig {
+    /// The order of the filters, which are enabled and their options.
+    #[serde(flatten)]
+    pub pipeline: PipelineConfig,
+    /// External filters by name, e.g.
+    /// `smallcaps = { command = ["python3", "filters/smallcaps.py"] }`.
+    ///
+    /// An external filter with the name of a built-in filter replaces it.
+    #[serde(default)]
+    pub external: BTreeMap<String, FilterCommand>,
+    /// Seconds after which external filters are killed.
+    #[serde(default = "default_filter_timeout")]
+    pub timeout: f64,
+}
+
+impl Default for FiltersConfig {
+    fn default() -> Self {
+        Self {
+            pipeline: PipelineConfig::default(),
+            external: BTreeMap::new(),
+            timeout: default_filter_timeout(),
+        }
+    }
+}
+
+fn default_filter_timeout() -> f64 {
+    30.0
+}
+
+impl FiltersConfig {
+    /// The timeout for external filters.
+    pub fn timeout(&self) -> Result<Duration> {
+        timeout("filter", self.timeout)
+    }
+
+    /// Check that every external filter has a command.
+    fn check_external(&self) -> Result<()> {
+        for (name, filter) in &self.external {
+            if filter.command.is_empty() {
+                bail!("empty command for external filter `{}`", name);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Configuration of how errors in notes are handled.
+///
+/// Development builds are the ones of `watch` and `serve`, production builds
+/// the ones of `build`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ErrorConfig {
+    #[serde(default = "default_development_errors")]
+    pub development: ErrorMode,
+    #[serde(default = "default_production_errors")]
+    pub production: ErrorMode,
+}
+
+impl Default for ErrorConfig {
+    fn default() -> Self {
+        Self {
+            development: default_development_errors(),
+            production: default_production_errors(),
+        }
+    }
+}
+
+fn default_development_errors() -> ErrorMode {
+    ErrorMode::Details
+}
+
+fn default_production_errors() -> ErrorMode {
+    ErrorMode::Placeholder
+}
+
+/// How errors in notes are handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorMode {
+    /// Show the error message in place of the failing element.
+    #[default]
+    Message,
+    /// Show a collapsible block with the error message, the failing source and
+    /// the chain of errors.
+    Details,
+    /// Show a neutral placeholder in place of the failing element.
+    Placeholder,
+    /// Fail the build.
+    Fail,
+}
+
+impl ErrorMode {
+    /// How errors are presented in the notes.
+    pub fn style(self) -> ErrorStyle {
+        match self {
+            ErrorMode::Message | ErrorMode::Fail => ErrorStyle::Message,
+            ErrorMode::Details => ErrorStyle::Details,
+            ErrorMode::Placeholder => ErrorStyle::Placeholder,
+        }
+    }
+}
+
+/// Schema of the custom fields of the frontmatter, e.g.
+/// `description = { type = "string", required = true }`.
+///
+/// Fields that are not in the schema are reported, so that misspelled fields
+/// are noticed.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SchemaConfig {
+    /// Whether fields that do not match the schema fail the build.
+    #[serde(default)]
+    pub level: SchemaLevel,
+    /// The custom fields by name.
+    #[serde(default)]
+    pub fields: BTreeMap<String, FieldSchema>,
+}
+
+/// How fields that do not match the schema are reported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SchemaLevel {
+    /// Print a warning.
+    #[default]
+    Warn,
+    /// Print an error and fail the build.
+    Error,
+}
+
+/// Schema of a custom field of the frontmatter.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FieldSchema {
+    /// The type of the value. Values of any type are allowed by default.
+    #[serde(default, rename = "type")]
+    pub kind: Option<FieldType>,
+    /// Whether notes must have the field.
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// The type of a custom field of the frontmatter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    String,
+    Number,
+    Integer,
+    Boolean,
+    Array,
+    Table,
+}
+
+impl FieldType {
+    /// Whether the value has this type.
+    pub fn matches(self, value: &Value) -> bool {
+        match self {
+            FieldType::String => value.is_string(),
+            FieldType::Number => value.is_number(),
+            FieldType::Integer => value.is_i64() || value.is_u64(),
+            FieldType::Boolean => value.is_boolean(),
+            FieldType::Array => value.is_array(),
+            FieldType::Table => value.is_object(),
+        }
+    }
+}
+
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FieldType::String => "a string",
+            FieldType::Number => "a number",
+            FieldType::Integer => "an integer",
+            FieldType::Boolean => "a boolean",
+            FieldType::Array => "an array",
+            FieldType::Table => "a table",
+        };
+
+        f.write_str(name)
+    }
+}
+
+impl SchemaConfig {
+    /// Check the custom fields of a note against the schema.
+    pub fn check(&self, extra: &BTreeMap<String, Value>) -> Vec<SchemaError> {
+        let mut errors = Vec::new();
+
+        for (name, value) in extra {
+            match self.fields.get(name).map(|field| field.kind) {
+                None => errors.push(SchemaError::UnknownField(name.clone())),
+                Some(Some(kind)) if !kind.matches(value) => {
+                    errors.push(SchemaError::WrongType(name.clone(), kind))
+                }
+                Some(_) => {}
+            }
+        }
+
+        for (name, field) in &self.fields {
+            if field.required && !extra.contains_key(name) {
+                errors.push(SchemaError::MissingField(name.clone()));
+            }
+        }
+
+        errors
+    }
+}
+
+/// A custom field of the frontmatter that does not match the schema.
+#[derive(Debug, Clone, Error)]
+pub enum SchemaError {
+    #[error("unknown field `{0}` in frontmatter")]
+    UnknownField(String),
+    #[error("field `{0}` in frontmatter must be {1}")]
+    WrongType(String, FieldType),
+    #[error("missing field `{0}` in frontmatter")]
+    MissingField(String),
+}
+
+impl SchemaError {
+    /// The name of the field.
+    pub fn field(&self) -> &str {
+        match self {
+            SchemaError::UnknownField(name)
+            | SchemaError::WrongType(name, _)
+            | SchemaError::MissingField(name) => name,
+        }
+    }
+}
+
+/// Configuration of code blocks that are rendered as diagrams.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiagramConfig {
+    /// Tools by the language of the code blocks they render, e.g.
+    /// `dot = { command = ["dot", "-Tsvg"] }`.
+    #[serde(default)]
+    pub tools: HashMap<String, DiagramTool>,
+    /// Seconds after which tools are killed.
+    #[serde(default = "default_diagram_timeout")]
+    pub timeout: f64,
+    /// File to keep rendered diagrams in between builds, e.g. `.cache/diagrams.json`.
+    #[serde(default)]
+    pub cache_file: Option<PathBuf>,
+    /// Whether to leave out fonts of diagrams that an earlier figure on the
+    /// page already embeds.
+    #[serde(default)]
+    pub dedup_fonts: bool,
+}
+
+impl Default for DiagramConfig {
+    fn default() -> Self {
+        Self {
+            tools: HashMap::new(),
+            timeout: default_diagram_timeout(),
+            cache_file: None,
+            dedup_fonts: false,
+        }
+    }
+}
+
+fn default_diagram_timeout() -> f64 {
+    10.0
+}
+
+impl DiagramConfig {
+    /// The timeout for tools.
+    pub fn timeout(&self) -> Result<Duration> {
+        timeout("diagram", self.timeout)
+    }
+
+    /// Check that every tool has a command.
+    fn check_tools(&self) -> Result<()> {
+        for (language, tool) in &self.tools {
+            if tool.command.is_empty() {
+                bail!("empty command for diagram tool `{}`", language);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Limits of user scripts, so that a script that loops or recurses without end
+/// fails instead of hanging the build.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScriptsConfig {
+    /// Operations a script may run per call, 0 for no limit.
+    #[serde(default = "default_max_operations")]
+    pub max_operations: u64,
+    /// Depth of nested function calls.
+    #[serde(default = "default_max_call_levels")]
+    pub max_call_levels: usize,
+    /// Depth of nested expressions at the top level of scripts.
+    #[serde(default = "default_max_expr_depth")]
+    pub max_expr_depth: usize,
+    /// Depth of nested expressions in functions.
+    #[serde(default = "default_max_function_expr_depth")]
+    pub max_function_expr_depth: usize,
+}
+
+impl Default for ScriptsConfig {
+    fn default() -> Self {
+        Self {
+            max_operations: default_max_operations(),
+            max_call_levels: default_max_call_levels(),
+            max_expr_depth: default_max_expr_depth(),
+            max_function_expr_depth: default_max_function_expr_depth(),
+        }
+    }
+}
+
+fn default_max_operations() -> u64 {
+    1_000_000
+}
+
+fn default_max_call_levels() -> usize {
+    64
+}
+
+fn default_max_expr_depth() -> usize {
+    64
+}
+
+fn default_max_function_expr_depth() -> usize {
+    32
+}
+
+/// Configuration of code blocks that are run at build time.
 #[derive(Debug, Clone, Deserialize)]
-pub struct Config {}
+pub struct ExecConfig {
+    /// Interpreters by the language of the code blocks they run, e.g.
+    /// `python = { command = ["python3"], extension = "py" }`.
+    ///
+    /// Only code blocks in these languages can be run.
+    #[serde(default)]
+    pub interpreters: HashMap<String, Interpreter>,
+    /// Seconds after which programs are killed.
+    #[serde(default = "default_exec_timeout")]
+    pub timeout: f64,
+    /// Language of the code blocks with the output of programs, which are
+    /// highlighted as such, e.g. `console`.
+    #[serde(default = "default_exec_output_language")]
+    pub output_language: String,
+    /// File to keep the outputs in between builds, e.g. `.cache/exec.json`.
+    #[serde(default)]
+    pub cache_file: Option<PathBuf>,
+}
 
-impl Default for Config {
+impl Default for ExecConfig {
     fn default() -> Self {
-        Self {}
+        Self {
+            interpreters: HashMap::new(),
+            timeout: default_exec_timeout(),
+            output_language: default_exec_output_language(),
+            cache_file: None,
+        }
+    }
+}
+
+fn default_exec_timeout() -> f64 {
+    10.0
+}
+
+fn default_exec_output_language() -> String {
+    DEFAULT_OUTPUT_LANGUAGE.into()
+}
+
+impl ExecConfig {
+    /// The timeout for programs.
+    pub fn timeout(&self) -> Result<Duration> {
+        timeout("exec", self.timeout)
+    }
+}
+
+/// A timeout in seconds, which must be a non-negative number.
+fn timeout(name: &str, seconds: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(seconds)
+        .with_context(|| format!("invalid {} timeout `{}`", name, seconds))
+}
+
+/// Syntax highlighting configuration.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HighlightConfig {
+    /// Theme for code blocks, either the name of a vendored Inkjet theme or
+    /// the path to a Helix theme file. No stylesheet is generated without it.
+    #[serde(default)]
+    pub theme: Option<String>,
+    /// Theme for code blocks when the dark color scheme is preferred.
+    #[serde(default)]
+    pub dark_theme: Option<String>,
+    /// Path of the generated stylesheet, relative to the output directory.
+    #[serde(default = "default_highlight_stylesheet")]
+    pub stylesheet: PathBuf,
+    /// Names of languages in code blocks mapped to the language to highlight
+    /// them as, e.g. `jsonc = "json"`.
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    /// Language to highlight code blocks in unsupported languages as.
+    #[serde(default)]
+    pub fallback: Option<String>,
+}
+
+impl Default for HighlightConfig {
+    fn default() -> Self {
+        Self {
+            theme: None,
+            dark_theme: None,
+            stylesheet: default_highlight_stylesheet(),
+            aliases: HashMap::new(),
+            fallback: None,
+        }
+    }
+}
+
+fn default_highlight_stylesheet() -> PathBuf {
+    HIGHLIGHT_STYLESHEET.into()
+}
+
+/// Site-wide math configuration.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MathConfig {
+    /// Macros available in every note.
+    ///
+    /// These take precedence over the macros in [`MathConfig::macros_file`] and
+    /// are overridden by the macros in a note's frontmatter.
+    #[serde(default)]
+    pub macros: HashMap<String, String>,
+    /// File with TeX macro definitions available in every note.
+    #[serde(default)]
+    pub macros_file: Option<PathBuf>,
+    /// Which macro definitions in math persist through the rest of a note.
+    #[serde(default)]
+    pub persist_macros: MacroPersistence,
+    /// File to keep rendered math in between builds, e.g. `.cache/katex.json`.
+    #[serde(default)]
+    pub cache_file: Option<PathBuf>,
+    /// What to try, in order, when KaTeX fails to render math.
+    #[serde(default)]
+    pub fallback: Vec<MathFallback>,
+    /// Preamble for math that falls back to LaTeX.
+    #[serde(default)]
+    pub latex_preamble: Option<String>,
+    /// Whether to leave out fonts of math rendered by LaTeX that an earlier
+    /// figure on the page already embeds.
+    #[serde(default)]
+    pub dedup_fonts: bool,
+    /// Options passed to KaTeX.
+    #[serde(flatten)]
+    pub katex: KatexOptions,
+}
+
+/// Options passed to KaTeX.
+///
+/// See <https://katex.org/docs/options.html> for their meaning. Options that
+/// are not set fall back to the site-wide options, and then to KaTeX's defaults.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct KatexOptions {
+    /// The markup to generate, HTML by default.
+    #[serde(default)]
+    pub output: Option<KatexOutput>,
+    /// Whether to make display math flush left.
+    #[serde(default)]
+    pub fleqn: Option<bool>,
+    /// Whether to render equation tags on the left.
+    #[serde(default)]
+    pub leqno: Option<bool>,
+    /// Whether invalid math is an error, or rendered in red instead.
+    #[serde(default)]
+    pub throw_on_error: Option<bool>,
+    /// Minimum thickness of fraction lines and the like, in `em`.
+    #[serde(default)]
+    pub min_rule_thickness: Option<f64>,
+    /// Whether to allow commands like `\href` and `\htmlClass`.
+    #[serde(default)]
+    pub trust: Option<bool>,
+}
+
+/// The markup generated by KaTeX.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KatexOutput {
+    /// HTML only.
+    #[default]
+    Html,
+    /// MathML only.
+    Mathml,
+    /// HTML for display, and MathML for accessibility.
+    HtmlAndMathml,
+}
+
+impl KatexOptions {
+    /// Combine with the `defaults` for all options that are not set.
+    pub fn or(&self, defaults: &Self) -> Self {
+        Self {
+            output: self.output.or(defaults.output),
+            fleqn: self.fleqn.or(defaults.fleqn),
+            leqno: self.leqno.or(defaults.leqno),
+            throw_on_error: self.throw_on_error.or(defaults.throw_on_error),
+            min_rule_thickness: self.min_rule_thickness.or(defaults.min_rule_thickness),
+            trust: self.trust.or(defaults.trust),
+        }
+    }
+
+    /// Convert to the options of the `katex` crate.
+    pub fn to_opts(&self) -> katex::Opts {
+        let output_type = match self.output.unwrap_or_default() {
+            KatexOutput::Html => katex::OutputType::Html,
+            KatexOutput::Mathml => katex::OutputType::Mathml,
+            KatexOutput::HtmlAndMathml => katex::OutputType::HtmlAndMathml,
+        };
+
+        let mut opts = katex::Opts::default();
+        opts.set_output_type(output_type);
+
+        if let Some(fleqn) = self.fleqn {
+            opts.set_fleqn(fleqn);
+        }
+
+        if let Some(leqno) = self.leqno {
+            opts.set_leqno(leqno);
+        }
+
+        if let Some(throw_on_error) = self.throw_on_error {
+            opts.set_throw_on_error(throw_on_error);
+        }
+
+        if let Some(min_rule_thickness) = self.min_rule_thickness {
+            opts.set_min_rule_thickness(min_rule_thickness);
+        }
+
+        if let Some(trust) = self.trust {
+            opts.set_trust(trust);
+        }
+
+        opts
     }
 }
 
 impl Config {
+    /// Load the configuration, falling back to the default if there is none.
     pub fn load() -> Result<Self> {
-        let content = std::fs::read_to_string("./scribe-notes.toml")?;
-        let config = toml::from_str(&content)?;
+        let path = Path::new(CONFIG_FILE);
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(path)?;
+        let mut config: Self = toml::from_str(&content)?;
+        config.math.load_macros_file()?;
+        config.highlight.aliases()?;
+        config.highlight.fallback()?;
+        config.exec.timeout()?;
+        config.diagrams.timeout()?;
+        config.diagrams.check_tools()?;
+        config.filters.timeout()?;
+        config.filters.check_external()?;
+        config.source = content;
         Ok(config)
     }
 }
 
-pub const TEMPLATES_DIR: &'static str = "templates/";
-pub const NOTES_INPUT_DIR: &'static str = "notes/";
-pub const NOTES_OUTPUT_DIR: &'static str = "dist/notes/";
-pub const DIST_DIR: &'static str = "dist/";
-pub const ASSETS_DIR: &'static str = "assets/";
+impl HighlightConfig {
+    /// Generate the stylesheet for the configured themes, if any.
+    pub fn stylesheet(&self) -> Result<Option<String>> {
+        let Some(theme) = &self.theme else {
+            return Ok(None);
+        };
+
+        let light = load_theme(theme)?;
+        let dark = self.dark_theme.as_deref().map(load_theme).transpose()?;
+        Ok(Some(theme_css(&light, dark.as_ref())))
+    }
+
+    /// The languages the aliases stand for.
+    pub fn aliases(&self) -> Result<HashMap<String, Language>> {
+        self.aliases
+            .iter()
+            .map(|(alias, name)| {
+                let language = Language::from_token(name).with_context(|| {
+                    format!("unsupported language `{}` for alias `{}`", name, alias)
+                })?;
+                Ok((alias.clone(), language))
+            })
+            .collect()
+    }
+
+    /// The fallback language, if any.
+    pub fn fallback(&self) -> Result<Option<Language>> {
+        self.fallback
+            .as_deref()
+            .map(|name| {
+                Language::from_token(name)
+                    .with_context(|| format!("unsupported fallback language `{}`", name))
+            })
+            .transpose()
+    }
+}
+
+impl MathConfig {
+    /// Merge the macros from the macros file into [`MathConfig::macros`].
+    fn load_macros_file(&mut self) -> Result<()> {
+        if let Some(path) = &self.macros_file {
+            let source = std::fs::read_to_string(path)
+                .with_context(|| format!("error reading macros file {}", path.display()))?;
+            let mut macros = parse_macros(&source)
+                .with_context(|| format!("error in macros file {}", path.display()))?;
+            macros.extend(std::mem::take(&mut self.macros));
+            self.macros = macros;
+        }
+
+        check_macros(&self.macros)?;
+        Ok(())
+    }
+}
+
+pub const CONFIG_FILE: &str = "./scribe-notes.toml";
+pub const TEMPLATES_DIR: &str = "templates/";
+pub const NOTES_INPUT_DIR: &str = "notes/";
+pub const NOTES_OUTPUT_DIR: &str = "dist/notes/";
+pub const DIST_DIR: &str = "dist/";
+pub const HIGHLIGHT_STYLESHEET: &str = "highlight.css";
+pub const ASSETS_DIR: &str = "assets/";
+pub const SCRIPTS_DIR: &str = "scripts/";
+pub const DEFAULTS_FILE: &str = "_defaults.yaml";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(toml: &str) -> SchemaConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn extra(toml: &str) -> BTreeMap<String, Value> {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn schema_accepts_matching_fields() {
+        let schema = schema(
+            "fields.author = { type = \"string\", required = true }\n\
+             fields.weight = { type = \"number\" }\n\
+             fields.anything = {}",
+        );
+        let extra = extra("author = \"A\"\nweight = 2\nanything = [1]");
+
+        assert!(schema.check(&extra).is_empty());
+    }
+
+    #[test]
+    fn schema_reports_mismatched_fields() {
+        let schema = schema(
+            "fields.author = { required = true }\n\
+             fields.count = { type = \"integer\" }",
+        );
+        let extra = extra("count = 1.5\nother = true");
+
+        let errors: Vec<_> = schema
+            .check(&extra)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        assert_eq!(
+            errors,
+            [
+                "field `count` in frontmatter must be an integer",
+                "unknown field `other` in frontmatter",
+                "missing field `author` in frontmatter",
+            ]
+        );
+    }
+}
@@ -0,0 +1,271 @@
+//! Default frontmatter of notes, from `_defaults.yaml` files in the notes
+//! directory and the `[defaults]` of the config.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use glob::Pattern;
+use tera::Value;
+use tracing::debug;
+
+use crate::config::DEFAULTS_FILE;
+
+/// Default frontmatter of the notes.
+///
+/// The defaults of a note are, from lowest to highest precedence:
+///
+/// - the `[defaults]` of the config whose globs match the path of the note,
+///   with longer globs taking precedence,
+/// - the `_defaults.yaml` files of the directories containing the note, with
+///   inner directories taking precedence.
+#[derive(Debug, Clone, Default)]
+pub struct Defaults {
+    globs: Vec<(Pattern, Value)>,
+    files: Vec<DefaultsFile>,
+}
+
+/// A `_defaults.yaml` file.
+#[derive(Debug, Clone)]
+struct DefaultsFile {
+    /// The directory whose notes the defaults apply to.
+    dir: PathBuf,
+    path: PathBuf,
+    source: String,
+    value: Value,
+}
+
+/// Where defaults come from.
+#[derive(Debug, Clone, Copy)]
+pub enum DefaultsSource<'a> {
+    /// The `[defaults]` of the config for a glob.
+    Glob(&'a str),
+    /// A `_defaults.yaml` file, with its content.
+    File { path: &'a Path, source: &'a str },
+}
+
+impl Defaults {
+    /// Load the defaults of the notes in `input_dir`, and the defaults from the
+    /// config by glob, e.g. `"notes/physics/*" = { math.macros = { ... } }`.
+    pub fn load(input_dir: &Path, config: &BTreeMap<String, Value>) -> Result<Self> {
+        let mut globs = config
+            .iter()
+            .map(|(glob, value)| {
+                let pattern = Pattern::new(glob)
+                    .with_context(|| format!("invalid glob `{}` in defaults", glob))?;
+                Ok((pattern, value.clone()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        globs.sort_by_key(|(pattern, _)| pattern.as_str().len());
+
+        let pattern = input_dir.join("**").join(DEFAULTS_FILE);
+        let mut files = Vec::new();
+
+        for entry in glob::glob(&pattern.to_string_lossy())? {
+            let path = entry?;
+            debug!("loading defaults: {}", path.display());
+
+            let source = fs::read_to_string(&path)
+                .with_context(|| format!("error reading defaults file {}", path.display()))?;
+            let value = serde_yaml_ng::from_str(&source)
+                .with_context(|| format!("error in defaults file {}", path.display()))?;
+            let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+            files.push(DefaultsFile {
+                dir,
+                path,
+                source,
+                value,
+            });
+        }
+
+        // Outer directories first.
+        files.sort_by_key(|file| file.dir.components().count());
+
+        Ok(Self { globs, files })
+    }
+
+    /// The defaults of the note at `path`, in the order they are merged.
+    pub fn for_note(&self, path: &Path) -> Vec<Value> {
+        self.matching(path)
+            .map(|(_, value)| value.clone())
+            .collect()
+    }
+
+    /// Where the top-level `field` of the note at `path` is set by defaults,
+    /// if it is, taking the defaults that take precedence.
+    pub fn field_source(&self, path: &Path, field: &str) -> Option<DefaultsSource<'_>> {
+        self.matching(path)
+            .rev()
+            .find(|(_, value)| value.get(field).is_some())
+            .map(|(source, _)| source)
+    }
+
+    /// The defaults of the note at `path` with their sources, in the order
+    /// they are merged.
+    fn matching(
+        &self,
+        path: &Path,
+    ) -> impl DoubleEndedIterator<Item = (DefaultsSource<'_>, &Value)> {
+        let globs = self
+            .globs
+            .iter()
+            .filter(move |(pattern, _)| pattern.matches_path(path))
+            .map(|(pattern, value)| (DefaultsSource::Glob(pattern.as_str()), value));
+
+        let files = self
+            .files
+            .iter()
+            .filter(move |file| path.starts_with(&file.dir))
+            .map(|file| {
+                let source = DefaultsSource::File {
+                    path: &file.path,
+                    source: &file.source,
+                };
+                (source, &file.value)
+            });
+
+        globs.chain(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use scribe_common::djot::parse_frontmatter_with_defaults;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::header::Header;
+
+    /// Notes with `_defaults.yaml` files in `notes/` and `notes/physics/`.
+    fn notes_dir() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let physics = dir.path().join("notes").join("physics");
+        fs::create_dir_all(&physics).unwrap();
+
+        let notes_defaults = "title: Note\ndraft: true\nmath:\n  macros:\n    \\R: \\mathbb{R}\n";
+        let physics_defaults = "tags: [physics]\nmath:\n  macros:\n    \\hbar: \\hslash\n";
+        fs::write(dir.path().join("notes").join(DEFAULTS_FILE), notes_defaults).unwrap();
+        fs::write(physics.join(DEFAULTS_FILE), physics_defaults).unwrap();
+
+        dir
+    }
+
+    fn config(toml: &str) -> BTreeMap<String, Value> {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn header(defaults: &Defaults, path: &Path, source: &str) -> Header {
+        let defaults = defaults.for_note(path);
+        parse_frontmatter_with_defaults::<Header>(source, &defaults)
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn defaults_files_in_subdirectories() {
+        let dir = notes_dir();
+        let notes = dir.path().join("notes");
+        let defaults = Defaults::load(&notes, &BTreeMap::new()).unwrap();
+
+        assert_eq!(defaults.for_note(&notes.join("a.dj")).len(), 1);
+        assert_eq!(defaults.for_note(&notes.join("physics/b.dj")).len(), 2);
+        assert_eq!(
+            defaults.for_note(&notes.join("physics/quantum/c.dj")).len(),
+            2
+        );
+        assert!(defaults.for_note(&dir.path().join("d.dj")).is_empty());
+    }
+
+    #[test]
+    fn merge_defaults_into_header() {
+        let dir = notes_dir();
+        let notes = dir.path().join("notes");
+        let config = config("\"*/physics/*\" = { title = \"Physics\", author = \"A\" }");
+        let defaults = Defaults::load(&notes, &config).unwrap();
+
+        let header = header(
+            &defaults,
+            &notes.join("physics/b.dj"),
+            "---\ndraft: false\n---\n",
+        );
+
+        // The files take precedence over the config, the note over both.
+        assert_eq!(header.title, "Note");
+        assert!(!header.draft);
+        assert_eq!(header.extra["author"], "A");
+        assert_eq!(header.extra["tags"], Value::Array(vec!["physics".into()]));
+        assert_eq!(header.math.macros.len(), 2);
+    }
+
+    #[test]
+    fn inner_defaults_take_precedence() {
+        let dir = notes_dir();
+        let notes = dir.path().join("notes");
+        fs::write(
+            notes.join("physics").join(DEFAULTS_FILE),
+            "title: Physics\n",
+        )
+        .unwrap();
+        let defaults = Defaults::load(&notes, &BTreeMap::new()).unwrap();
+
+        assert_eq!(header(&defaults, &notes.join("a.dj"), "").title, "Note");
+        assert_eq!(
+            header(&defaults, &notes.join("physics/b.dj"), "").title,
+            "Physics"
+        );
+    }
+
+    #[test]
+    fn longer_globs_take_precedence() {
+        let config = config(
+            "\"notes/*\" = { title = \"All\" }\n\"notes/physics/*\" = { title = \"Physics\" }",
+        );
+        let defaults = Defaults::load(Path::new("missing"), &config).unwrap();
+
+        let header = header(&defaults, Path::new("notes/physics/b.dj"), "");
+        assert_eq!(header.title, "Physics");
+    }
+
+    #[test]
+    fn field_sources() {
+        let dir = notes_dir();
+        let notes = dir.path().join("notes");
+        let config = config("\"*/physics/*\" = { author = \"A\" }");
+        let defaults = Defaults::load(&notes, &config).unwrap();
+        let path = notes.join("physics/b.dj");
+
+        assert!(matches!(
+            defaults.field_source(&path, "math"),
+            Some(DefaultsSource::File { path, .. }) if path == notes.join("physics").join(DEFAULTS_FILE)
+        ));
+        assert!(matches!(
+            defaults.field_source(&path, "author"),
+            Some(DefaultsSource::Glob("*/physics/*"))
+        ));
+        assert!(defaults.field_source(&path, "date").is_none());
+    }
+
+    #[test]
+    fn null_unsets_defaults() {
+        let dir = notes_dir();
+        let notes = dir.path().join("notes");
+        let config = config("\"*\" = { date = \"2024-01-01\" }");
+        let defaults = Defaults::load(&notes, &config).unwrap();
+        let path = notes.join("physics/b.dj");
+
+        // Optional fields become `None`.
+        let unset = header(&defaults, &path, "---\ndate: null\n---\n");
+        assert_eq!(unset.date, None);
+
+        // Other fields get their default value.
+        let source = "---\ntitle: null\ndraft: ~\nmath:\n  macros: null\n---\n";
+        let header = header(&defaults, &path, source);
+        assert_eq!(header.title, "");
+        assert!(!header.draft);
+        assert!(header.math.macros.is_empty());
+        assert_eq!(header.date.as_deref(), Some("2024-01-01"));
+    }
+}
@@ -0,0 +1,66 @@
+//! Diagnostics for errors in notes, printed like the ones of rustc.
+
+use std::{ops::Range, path::Path};
+
+/// Format a diagnostic for the byte `range` of the note at `path`.
+///
+/// The diagnostic shows the first line of the range, with the range marked:
+///
+/// ```text
+/// error: reference to unknown equation `euler`
+///   --> notes/math.dj:12:5
+///    |
+/// 12 | see $`\eqref{euler}`
+///    |     ^^^^^^^^^^^^^^^^
+/// ```
+pub fn format_diagnostic(path: &Path, source: &str, message: &str, range: Range<usize>) -> String {
+    format_with_level("error", path, source, message, range)
+}
+
+/// Format a warning for the byte `range` of the note at `path`, like
+/// [`format_diagnostic`].
+pub fn format_warning(path: &Path, source: &str, message: &str, range: Range<usize>) -> String {
+    format_with_level("warning", path, source, message, range)
+}
+
+fn format_with_level(
+    level: &str,
+    path: &Path,
+    source: &str,
+    message: &str,
+    range: Range<usize>,
+) -> String {
+    let start = range.start.min(source.len());
+    let line_start = source[..start].rfind('\n').map_or(0, |index| index + 1);
+    let line_end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |index| start + index);
+    let end = range.end.clamp(start, line_end);
+
+    let line_number = source[..start].matches('\n').count() + 1;
+    let column = source[line_start..start].chars().count() + 1;
+    let gutter = " ".repeat(line_number.to_string().len());
+
+    // Keep tabs, so that the marker lines up with the source line.
+    let padding: String = source[line_start..start]
+        .chars()
+        .map(|char| if char == '\t' { '\t' } else { ' ' })
+        .collect();
+    let marker = "^".repeat(source[start..end].chars().count().max(1));
+
+    format!(
+        "{}: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}\n",
+        level,
+        message,
+        gutter,
+        path.display(),
+        line_number,
+        column,
+        gutter,
+        line_number,
+        &source[line_start..line_end],
+        gutter,
+        padding,
+        marker
+    )
+}
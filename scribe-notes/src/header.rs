@@ -1,20 +1,55 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use scribe_common::djot::{MacroPersistence, MathFallback, PipelineConfig};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use tera::Value;
 
+use crate::config::KatexOptions;
+
+/// The frontmatter of a note.
+///
+/// A field set to `null`, e.g. to unset a default, gets its default value:
+/// `None` for optional fields, an empty title, `draft: false` and so on.
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct Header {
-    #[serde(default)]
+    #[serde(default, deserialize_with = "null_as_default")]
     pub title: String,
     #[serde(default)]
     pub date: Option<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "null_as_default")]
     pub math: MathHeader,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "null_as_default")]
     pub draft: bool,
+    /// The template to render the note with, instead of `note.html`.
+    #[serde(default)]
+    pub template: Option<String>,
+    /// Overrides the site-wide filter configuration, e.g. to disable a filter.
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub filters: PipelineConfig,
+    /// Custom fields, e.g. `description`, which are available in templates.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct MathHeader {
-    #[serde(default)]
+    #[serde(default, deserialize_with = "null_as_default")]
     pub macros: HashMap<String, String>,
+    /// Overrides the site-wide setting from the config.
+    #[serde(default)]
+    pub persist_macros: Option<MacroPersistence>,
+    /// Overrides the site-wide fallbacks from the config.
+    #[serde(default)]
+    pub fallback: Option<Vec<MathFallback>>,
+    /// Overrides the site-wide KaTeX options from the config.
+    #[serde(flatten)]
+    pub katex: KatexOptions,
+}
+
+/// Deserialize `null` as the default value of a field that is not optional.
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
 }
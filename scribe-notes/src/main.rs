@@ -1,21 +1,31 @@
 use std::{
+    collections::HashSet,
     path::{Path, PathBuf},
     sync::mpsc,
 };
 
 use anyhow::{Result, bail};
 use clap::Parser as _;
-use tracing::{error, info, instrument, trace};
+use scribe_common::cache::Cache;
+use scribe_common::djot::{KatexCache, THEMES};
+use tracing::{error, info, instrument, trace, warn};
 
 use crate::{
-    config::{ASSETS_DIR, DIST_DIR, NOTES_INPUT_DIR, NOTES_OUTPUT_DIR, TEMPLATES_DIR},
-    render::{copy_static_assets, render_index_file, render_note_files},
+    config::{
+        ASSETS_DIR, Config, DIST_DIR, NOTES_INPUT_DIR, NOTES_OUTPUT_DIR, SCRIPTS_DIR, TEMPLATES_DIR,
+    },
+    defaults::Defaults,
+    render::{BuildState, copy_static_assets, render_index_file, render_note_files},
+    scripts::Scripts,
     templates::Templates,
 };
 
 pub mod config;
+pub mod defaults;
+pub mod diagnostics;
 pub mod header;
 pub mod render;
+pub mod scripts;
 pub mod templates;
 
 #[derive(clap::Parser)]
@@ -37,6 +47,8 @@ pub enum Commands {
     Clean {},
     /// Creare a new note.
     New(NewCommand),
+    /// Print the stylesheet for a highlighting theme.
+    Theme(ThemeCommand),
 }
 
 /// Create a new note.
@@ -50,6 +62,22 @@ pub struct NewCommand {
     edit: bool,
 }
 
+/// Print the stylesheet for a highlighting theme.
+#[derive(Debug, clap::Args)]
+pub struct ThemeCommand {
+    /// The theme, either the name of a vendored theme or a Helix theme file.
+    /// Defaults to the configured theme.
+    theme: Option<String>,
+
+    /// The theme to use when the dark color scheme is preferred.
+    #[clap(short = 'd', long)]
+    dark: Option<String>,
+
+    /// List the names of the vendored themes.
+    #[clap(short = 'l', long)]
+    list: bool,
+}
+
 #[tokio::main]
 pub async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
@@ -57,7 +85,7 @@ pub async fn main() -> Result<()> {
 
     match cli.command {
         Commands::Build {} => {
-            build()?;
+            build(false)?;
         }
         Commands::Watch {} => {
             watch()?;
@@ -73,24 +101,108 @@ pub async fn main() -> Result<()> {
         Commands::New(cmd) => {
             new_note(cmd)?;
         }
+        Commands::Theme(cmd) => {
+            theme(cmd)?;
+        }
     }
 
     Ok(())
 }
 
+/// Build the notes, returning the state shared by them.
+///
+/// Errors in the notes are handled as configured for development builds if
+/// `development` is set, and for production builds otherwise.
 #[instrument(name = "build")]
-fn build() -> Result<()> {
+fn build(development: bool) -> Result<BuildState> {
     info!("building notes...");
     let notes_input_dir: PathBuf = NOTES_INPUT_DIR.into();
     let notes_output_dir: PathBuf = NOTES_OUTPUT_DIR.into();
     let dist_dir: PathBuf = DIST_DIR.into();
     let assets_dir: PathBuf = ASSETS_DIR.into();
-    let templates = Templates::new()?;
+    let mut templates = Templates::new()?;
+    let config = Config::load()?;
+    let scripts = Scripts::load(SCRIPTS_DIR.as_ref(), &config.scripts)?;
+    templates.register_helpers(&scripts);
+    let defaults = Defaults::load(&notes_input_dir, &config.defaults)?;
 
-    render_index_file(&notes_input_dir, &notes_output_dir, &templates)?;
-    render_note_files(&notes_input_dir, &notes_output_dir, &templates)?;
+    let katex_cache = match &config.math.cache_file {
+        Some(path) => KatexCache::load(path)?,
+        None => KatexCache::new(),
+    };
+
+    let exec_cache = match &config.exec.cache_file {
+        Some(path) => Cache::load(path)?,
+        None => Cache::new(),
+    };
+
+    let diagram_cache = match &config.diagrams.cache_file {
+        Some(path) => Cache::load(path)?,
+        None => Cache::new(),
+    };
+
+    let errors = match development {
+        true => config.errors.development,
+        false => config.errors.production,
+    };
+
+    let state = BuildState {
+        katex_cache,
+        exec_cache,
+        diagram_cache,
+        errors,
+        scripts,
+        defaults,
+        ..Default::default()
+    };
+
+    render_index_file(&notes_input_dir, &notes_output_dir, &templates, &state)?;
+    render_note_files(
+        &notes_input_dir,
+        &notes_output_dir,
+        &templates,
+        &config,
+        &state,
+    )?;
     copy_static_assets(&assets_dir, &dist_dir)?;
-    Ok(())
+
+    if let Some(css) = config.highlight.stylesheet()? {
+        let path = dist_dir.join(&config.highlight.stylesheet);
+        info!("writing highlight stylesheet to: {}", path.display());
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(path, css)?;
+    }
+
+    if let Some(path) = &config.math.cache_file {
+        state.katex_cache.save(path)?;
+    }
+
+    if let Some(path) = &config.exec.cache_file {
+        state.exec_cache.save(path)?;
+    }
+
+    if let Some(path) = &config.diagrams.cache_file {
+        state.diagram_cache.save(path)?;
+    }
+
+    let unsupported = state.unsupported.languages();
+
+    if !unsupported.is_empty() {
+        let languages: Vec<_> = unsupported
+            .iter()
+            .map(|(language, count)| format!("{} ({} blocks)", language, count))
+            .collect();
+        warn!(
+            "code blocks in unsupported languages: {}",
+            languages.join(", ")
+        );
+    }
+
+    Ok(state)
 }
 
 fn watch() -> Result<()> {
@@ -108,14 +220,34 @@ fn watch() -> Result<()> {
         watcher.watch(ASSETS_DIR.as_ref(), RecursiveMode::Recursive)?;
         watcher.watch(TEMPLATES_DIR.as_ref(), RecursiveMode::Recursive)?;
 
+        if Path::new(SCRIPTS_DIR).exists() {
+            watcher.watch(SCRIPTS_DIR.as_ref(), RecursiveMode::Recursive)?;
+        }
+
+        // Files included into notes, which can be anywhere.
+        let mut included = HashSet::new();
+
         for res in rx {
             match res {
                 Ok(event) => {
                     trace!("watch event: {:?}", event);
-                    let result = build();
+                    let result = build(true);
+
+                    match result {
+                        Ok(state) => {
+                            for path in state.included.files() {
+                                if !path.exists() || included.contains(&path) {
+                                    continue;
+                                }
 
-                    if let Err(error) = result {
-                        error!("Error while building: {:?}", error);
+                                trace!("watching included file: {}", path.display());
+                                watcher.watch(&path, RecursiveMode::NonRecursive)?;
+                                included.insert(path);
+                            }
+                        }
+                        Err(error) => {
+                            error!("Error while building: {:?}", error);
+                        }
                     }
                 }
                 Err(e) => {
@@ -165,6 +297,32 @@ fn new_note(cmd: NewCommand) -> Result<()> {
     Ok(())
 }
 
+fn theme(cmd: ThemeCommand) -> Result<()> {
+    if cmd.list {
+        for (name, _) in THEMES {
+            println!("{}", name);
+        }
+
+        return Ok(());
+    }
+
+    let mut highlight = Config::load()?.highlight;
+
+    if let Some(theme) = cmd.theme {
+        highlight.theme = Some(theme);
+        highlight.dark_theme = cmd.dark;
+    } else if cmd.dark.is_some() {
+        highlight.dark_theme = cmd.dark;
+    }
+
+    let Some(css) = highlight.stylesheet()? else {
+        bail!("no theme given and none configured");
+    };
+
+    print!("{}", css);
+    Ok(())
+}
+
 fn open_editor(path: &Path) -> Result<()> {
     let Ok(editor) = std::env::var("EDITOR") else {
         info!("EDITOR environment variable not set");
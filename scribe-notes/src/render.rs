@@ -1,29 +1,84 @@
-use std::{fs, path::Path};
+use std::{
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
 
 use crate::{
+    config::{CONFIG_FILE, Config, ErrorMode, SchemaConfig, SchemaError, SchemaLevel},
+    defaults::{Defaults, DefaultsSource},
+    diagnostics::{format_diagnostic, format_warning},
     header::Header,
+    scripts::{ScriptTransforms, Scripts},
     templates::{NoteData, Templates},
 };
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use inkjet::Highlighter;
-use scribe_common::djot::{DemoteHeadings, InkjetCode, KatexMath, ShowErrors, parse_frontmatter};
-use tracing::{Level, info, instrument};
+use scribe_common::cache::Cache;
+use scribe_common::djot::{
+    DEFAULT_LATEX_PREAMBLE, DemoteHeadingsFilter, Diagnostics, DiagramBlocks, ErrorStyle, Events,
+    ExecCode, ExternalFilter, Frontmatter, FrontmatterError, IncludeCode, IncludedFiles,
+    InkjetCode, KatexCache, KatexMath, NumberEquations, Pipeline, Severity, ShowErrors, Strings,
+    UnsupportedLanguages, check_macros, parse_frontmatter_with_defaults, split_frontmatter,
+};
+use scribe_common::tools::exec::ExecOutput;
+use scribe_common::tools::svg::{SharedSvgProcessor, SvgProcessor};
+use tracing::{info, instrument};
 
-#[instrument(err, skip(input_dir, output_dir, templates))]
-pub fn render_index_file(input_dir: &Path, output_dir: &Path, templates: &Templates) -> Result<()> {
-    let pattern = input_dir.join("*.dj");
-    let glob_pattern = pattern.to_string_lossy();
+/// State shared by the notes of a build.
+#[derive(Debug, Default)]
+pub struct BuildState {
+    /// Math rendered by KaTeX.
+    pub katex_cache: KatexCache,
+    /// Languages of code blocks that could not be highlighted.
+    pub unsupported: UnsupportedLanguages,
+    /// Files included into code blocks.
+    pub included: IncludedFiles,
+    /// Outputs of code blocks that are run.
+    pub exec_cache: Cache<ExecOutput>,
+    /// Rendered diagrams.
+    pub diagram_cache: Cache<String>,
+    /// How errors in the notes are handled.
+    pub errors: ErrorMode,
+    /// Transforms and template helpers defined by user scripts.
+    pub scripts: Scripts,
+    /// Default frontmatter of the notes.
+    pub defaults: Defaults,
+    /// Strings of elements changed by scripts and external filters.
+    pub strings: Strings,
+}
+
+/// The notes in `input_dir` and its subdirectories.
+fn note_files(input_dir: &Path) -> Result<Vec<PathBuf>> {
+    let pattern = input_dir.join("**").join("*.dj");
+    let files = glob::glob(&pattern.to_string_lossy())?.collect::<Result<_, _>>()?;
+    Ok(files)
+}
 
+#[instrument(err, skip(input_dir, output_dir, templates, state))]
+pub fn render_index_file(
+    input_dir: &Path,
+    output_dir: &Path,
+    templates: &Templates,
+    state: &BuildState,
+) -> Result<()> {
     let mut headers = Vec::new();
 
     // Create output directory if it doesn't exist.
     fs::create_dir_all(output_dir)?;
 
-    for entry in glob::glob(&glob_pattern)? {
-        let input_file = entry?;
+    for input_file in note_files(input_dir)? {
         let source = fs::read_to_string(&input_file).context("error reading note file")?;
-        let (header, _) = parse_frontmatter::<Header>(&source)?;
-        let link = format!("/notes/{}.html", input_file.file_stem().unwrap().display());
+        let defaults = state.defaults.for_note(&input_file);
+        let (header, _) = parse_frontmatter_with_defaults::<Header>(&source, &defaults)
+            .inspect_err(|error| report_frontmatter_error(&input_file, &source, error))?;
+        let mut rel_path = input_file.strip_prefix(input_dir)?.to_path_buf();
+        rel_path.set_extension("html");
+        let components: Vec<_> = rel_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect();
+        let link = format!("/notes/{}", components.join("/"));
         headers.push(NoteData { header, link });
     }
 
@@ -35,61 +90,411 @@ pub fn render_index_file(input_dir: &Path, output_dir: &Path, templates: &Templa
     Ok(())
 }
 
-#[instrument(err, skip(input_dir, output_dir, templates))]
-pub fn render_note_files(input_dir: &Path, output_dir: &Path, templates: &Templates) -> Result<()> {
-    let pattern = input_dir.join("*.dj");
-    let glob_pattern = pattern.to_string_lossy();
-
-    // Create output directory if it doesn't exist.
-    fs::create_dir_all(output_dir)?;
-
-    for entry in glob::glob(&glob_pattern)? {
-        let input_file = entry?;
+#[instrument(err, skip(input_dir, output_dir, templates, config, state))]
+pub fn render_note_files(
+    input_dir: &Path,
+    output_dir: &Path,
+    templates: &Templates,
+    config: &Config,
+    state: &BuildState,
+) -> Result<()> {
+    for input_file in note_files(input_dir)? {
         let rel_path = input_file.strip_prefix(input_dir)?;
         let mut output_path = output_dir.join(rel_path);
         output_path.set_extension("html");
-        render_note_file(&input_file, &output_path, templates)?;
+
+        // Create the directory of the note if it doesn't exist.
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        render_note_file(&input_file, &output_path, templates, config, state)?;
     }
 
     Ok(())
 }
 
-#[instrument(err, skip(templates, output_file))]
+#[instrument(err, skip(templates, output_file, config, state))]
 pub fn render_note_file(
     input_file: &Path,
     output_file: &Path,
     templates: &Templates,
+    config: &Config,
+    state: &BuildState,
 ) -> Result<()> {
     info!("rendering note...");
     let source = fs::read_to_string(input_file)?;
-    let html = render_note(&source, templates)?;
+    let base_dir = input_file.parent().unwrap_or(Path::new("."));
+    let defaults = state.defaults.for_note(input_file);
+    let diagnostics = Diagnostics::new();
+
+    let parsed = parse_frontmatter_with_defaults::<Header>(&source, &defaults);
+
+    if let Err(error) = &parsed {
+        report_frontmatter_error(input_file, &source, error);
+    }
+
+    let (header, _) = parsed?;
+
+    if let Some(schema) = &config.schema {
+        check_schema(
+            input_file,
+            &source,
+            &header,
+            &state.defaults,
+            &config.source,
+            schema,
+        )?;
+    }
+
+    let html = render_note(
+        &source,
+        header,
+        base_dir,
+        templates,
+        config,
+        state,
+        &diagnostics,
+    )?;
+
+    let diagnostics = diagnostics.diagnostics();
+
+    for diagnostic in &diagnostics {
+        let format = match diagnostic.severity {
+            Severity::Error => format_diagnostic,
+            Severity::Warning => format_warning,
+        };
+
+        eprintln!(
+            "{}",
+            format(
+                input_file,
+                &source,
+                &diagnostic.message,
+                diagnostic.range.clone()
+            )
+        );
+    }
+
+    let failed = diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error);
+
+    if state.errors == ErrorMode::Fail && failed {
+        bail!("errors in note {}", input_file.display());
+    }
+
     fs::write(output_file, html)?;
     Ok(())
 }
 
-pub fn render_note(source: &str, templates: &Templates) -> Result<String> {
-    let (header, body) = parse_frontmatter::<Header>(source)?;
+/// Check the custom fields of a note against the schema, printing a
+/// diagnostic for each field that does not match it.
+///
+/// Fields that the note gets from its defaults are reported where the
+/// defaults set them, in a `_defaults.yaml` file or the config.
+fn check_schema(
+    input_file: &Path,
+    source: &str,
+    header: &Header,
+    defaults: &Defaults,
+    config_source: &str,
+    schema: &SchemaConfig,
+) -> Result<()> {
+    let errors = schema.check(&header.extra);
+
+    for error in &errors {
+        let location = locate_schema_error(input_file, source, defaults, config_source, error);
+        let mut message = error.to_string();
+
+        if location.path != input_file {
+            message = format!(
+                "{} (in the defaults of note {})",
+                message,
+                input_file.display()
+            );
+        }
 
-    let katex_opts = katex::Opts::builder()
-        .macros(header.math.macros.clone())
-        .output_type(katex::OutputType::Html)
-        .build()
-        .unwrap();
+        let diagnostic = match schema.level {
+            SchemaLevel::Warn => format_warning,
+            SchemaLevel::Error => format_diagnostic,
+        };
+
+        eprintln!(
+            "{}",
+            diagnostic(location.path, location.source, &message, location.range)
+        );
+    }
+
+    if schema.level == SchemaLevel::Error && !errors.is_empty() {
+        bail!(
+            "frontmatter of note {} does not match the schema",
+            input_file.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// The file, its source and the range in it at which a schema error is
+/// reported.
+#[derive(Debug)]
+struct SchemaErrorLocation<'a> {
+    path: &'a Path,
+    source: &'a str,
+    range: Range<usize>,
+}
+
+/// Where to report a schema error of the note at `input_file`.
+///
+/// A field is reported where it is set: in the frontmatter, or else where the
+/// defaults set it, in a `_defaults.yaml` file or in `config_source`. A missing
+/// field is reported at the whole frontmatter.
+fn locate_schema_error<'a>(
+    input_file: &'a Path,
+    source: &'a str,
+    defaults: &'a Defaults,
+    config_source: &'a str,
+    error: &SchemaError,
+) -> SchemaErrorLocation<'a> {
+    let field = error.field();
+    let frontmatter = split_frontmatter(source).frontmatter;
+    let in_note = |frontmatter: Frontmatter| {
+        let range = field_range(frontmatter.content, field)?;
+        Some(frontmatter.offset + range.start..frontmatter.offset + range.end)
+    };
+
+    let (path, source, range) = match (
+        frontmatter.and_then(in_note),
+        defaults.field_source(input_file, field),
+    ) {
+        (Some(range), _) => (input_file, source, range),
+        (None, Some(DefaultsSource::File { path, source })) => {
+            let range = field_range(source, field).unwrap_or(0..0);
+            (path, source, range)
+        }
+        (None, Some(DefaultsSource::Glob(glob))) => {
+            let range = defaults_glob_range(config_source, glob);
+            (Path::new(CONFIG_FILE), config_source, range)
+        }
+        (None, None) => {
+            let range = frontmatter.map_or(0..0, |frontmatter| {
+                frontmatter.offset..frontmatter.offset + frontmatter.content.len()
+            });
+            (input_file, source, range)
+        }
+    };
+
+    SchemaErrorLocation {
+        path,
+        source,
+        range,
+    }
+}
+
+/// The range of the key of `glob` in the `[defaults]` table of the config, or
+/// else of the table's header.
+fn defaults_glob_range(config_source: &str, glob: &str) -> Range<usize> {
+    let keys = [format!("\"{}\"", glob), format!("'{}'", glob)];
+    let mut header = None;
+    let mut in_defaults = false;
+    let mut offset = 0;
+
+    for line in config_source.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let content = line.trim();
+
+        let key = if let Some(table) = content.strip_prefix('[') {
+            in_defaults = table == "defaults]";
+
+            if in_defaults {
+                let start = line_start + line.find('[').unwrap_or(0);
+                header = Some(start..start + content.len());
+            }
+
+            // A table per glob, as in `[defaults."notes/*"]`.
+            table
+                .strip_prefix("defaults.")
+                .and_then(|table| keys.iter().find(|key| table.starts_with(key.as_str())))
+        } else if in_defaults {
+            keys.iter().find(|key| content.starts_with(key.as_str()))
+        } else {
+            None
+        };
+
+        if let Some(key) = key {
+            let start = line_start + line.find(key.as_str()).unwrap_or(0);
+            return start..start + key.len();
+        }
+    }
+
+    header.unwrap_or(0..0)
+}
 
-    let highlighter = Highlighter::new();
+/// The range of the name of a top-level field in YAML or TOML `content`, if
+/// found.
+fn field_range(content: &str, name: &str) -> Option<Range<usize>> {
+    let mut offset = 0;
 
-    let parser = jotdown::Parser::new(body);
-    let parser = DemoteHeadings::new(parser, 1);
-    let parser = KatexMath::new(parser, katex_opts);
-    let parser = ShowErrors::new(parser);
-    let parser = InkjetCode::new(parser, highlighter);
-    let parser = ShowErrors::new(parser);
-    let body = jotdown::html::render_to_string(parser);
+    for line in content.split_inclusive('\n') {
+        let rest = line.strip_prefix(name).unwrap_or_default();
+
+        if rest.trim_start().starts_with([':', '=']) {
+            return Some(offset..offset + name.len());
+        }
+
+        offset += line.len();
+    }
+
+    None
+}
+
+/// Print a diagnostic for an error in the frontmatter of a note.
+fn report_frontmatter_error(input_file: &Path, source: &str, error: &FrontmatterError) {
+    let (Some(offset), Some(cause)) = (error.offset(), std::error::Error::source(error)) else {
+        return;
+    };
+
+    eprintln!(
+        "{}",
+        format_diagnostic(input_file, source, &cause.to_string(), offset..offset)
+    );
+}
+
+/// Render a note, resolving included files relative to `base_dir`.
+///
+/// The `header` is the frontmatter of the note, merged over its defaults.
+/// Errors shown in the note are recorded in `diagnostics`, with byte ranges in
+/// `source`.
+pub fn render_note<'a>(
+    source: &'a str,
+    header: Header,
+    base_dir: &'a Path,
+    templates: &Templates,
+    config: &'a Config,
+    state: &'a BuildState,
+    diagnostics: &'a Diagnostics,
+) -> Result<String> {
+    let split = split_frontmatter(source);
+    let (body, offset) = (split.body, split.body_offset);
+
+    // Macros from the frontmatter override the site-wide macros.
+    let mut macros = config.math.macros.clone();
+    macros.extend(header.math.macros.clone());
+    check_macros(&macros)?;
+
+    let katex_options = header.math.katex.or(&config.math.katex);
+    let katex_opts = katex_options.to_opts();
+
+    let persist_macros = header
+        .math
+        .persist_macros
+        .unwrap_or(config.math.persist_macros);
+    let fallback = header
+        .math
+        .fallback
+        .clone()
+        .unwrap_or_else(|| config.math.fallback.clone());
+    let latex_preamble = config
+        .math
+        .latex_preamble
+        .as_deref()
+        .unwrap_or(DEFAULT_LATEX_PREAMBLE);
+    let aliases = config.highlight.aliases()?;
+    let highlight_fallback = config.highlight.fallback()?;
+    let exec_timeout = config.exec.timeout()?;
+    let diagram_timeout = config.diagrams.timeout()?;
+    let filter_timeout = config.filters.timeout()?;
+    let style = state.errors.style();
+
+    // Math rendered by LaTeX and diagrams share the namespaces and fonts of
+    // their SVGs.
+    let math_svg =
+        SharedSvgProcessor::new(SvgProcessor::new("svg")).dedup_fonts(config.math.dedup_fonts);
+    let diagram_svg = math_svg.clone().dedup_fonts(config.diagrams.dedup_fonts);
+
+    let mut pipeline = Pipeline::new()
+        .with_filter("scripts", move |events: Events<'a>| -> Events<'a> {
+            let events = ScriptTransforms::new(events, state.scripts.clone(), &state.strings);
+            Box::new(show_errors(events, source, style, diagnostics))
+        })
+        .with_filter("headings", DemoteHeadingsFilter::new(1))
+        .with_filter("equations", move |events: Events<'a>| -> Events<'a> {
+            let events = NumberEquations::new(events);
+            Box::new(show_errors(events, source, style, diagnostics))
+        })
+        .with_filter("math", move |events: Events<'a>| -> Events<'a> {
+            let events = KatexMath::new(events, katex_opts)
+                .with_macros(macros)
+                .with_macro_persistence(persist_macros)
+                .with_cache(state.katex_cache.clone(), &katex_options)
+                .with_fallbacks(fallback)
+                .with_latex_preamble(latex_preamble)
+                .with_svg_processor(math_svg)
+                .with_source(source)
+                .with_diagnostics(diagnostics.clone());
+            Box::new(show_errors(events, source, style, diagnostics))
+        })
+        .with_filter("include", move |events: Events<'a>| -> Events<'a> {
+            let events = IncludeCode::new(events, base_dir).with_included(state.included.clone());
+            Box::new(show_errors(events, source, style, diagnostics))
+        })
+        .with_filter("exec", move |events: Events<'a>| -> Events<'a> {
+            let events = ExecCode::new(events, config.exec.interpreters.clone())
+                .with_timeout(exec_timeout)
+                .with_output_language(&config.exec.output_language)
+                .with_cache(state.exec_cache.clone());
+            Box::new(show_errors(events, source, style, diagnostics))
+        })
+        .with_filter("diagrams", move |events: Events<'a>| -> Events<'a> {
+            let events = DiagramBlocks::new(events, config.diagrams.tools.clone())
+                .with_timeout(diagram_timeout)
+                .with_cache(state.diagram_cache.clone())
+                .with_svg_processor(diagram_svg);
+            Box::new(show_errors(events, source, style, diagnostics))
+        })
+        .with_filter("highlight", move |events: Events<'a>| -> Events<'a> {
+            let events = InkjetCode::new(events, Highlighter::new())
+                .with_aliases(aliases)
+                .with_fallback(highlight_fallback)
+                .with_unsupported(state.unsupported.clone());
+            Box::new(show_errors(events, source, style, diagnostics))
+        });
+
+    for (name, command) in &config.filters.external {
+        pipeline = pipeline.with_filter(name, move |events: Events<'a>| -> Events<'a> {
+            let events = ExternalFilter::new(events, command.clone(), &state.strings)
+                .with_timeout(filter_timeout);
+            Box::new(show_errors(events, source, style, diagnostics))
+        });
+    }
+
+    let pipeline = pipeline.configure(&header.filters.or(&config.filters.pipeline))?;
+
+    let events = jotdown::Parser::new(body)
+        .into_offset_iter()
+        .map(move |(event, range)| (event, range.start + offset..range.end + offset));
+    let events = pipeline.apply(Box::new(events));
+    let body = jotdown::html::render_to_string(events.map(|(event, _)| event));
 
     let html = templates.render_note(&header, &body)?;
     Ok(html)
 }
 
+/// Show the errors of a filter in the note and record them in `diagnostics`.
+fn show_errors<'a, I>(
+    parser: I,
+    source: &'a str,
+    style: ErrorStyle,
+    diagnostics: &Diagnostics,
+) -> ShowErrors<'a, I> {
+    ShowErrors::new(parser)
+        .with_style(style)
+        .with_source(source)
+        .with_diagnostics(diagnostics.clone())
+}
+
 pub fn copy_static_assets(assets_dir: &Path, dist_dir: &Path) -> Result<()> {
     if !assets_dir.exists() {
         return Ok(());
@@ -117,3 +522,151 @@ pub fn copy_static_assets(assets_dir: &Path, dist_dir: &Path) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::config::DEFAULTS_FILE;
+
+    fn schema(toml: &str) -> SchemaConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    /// The errors of the note at `path` and the defaults of its directory.
+    fn check(path: &Path, source: &str, schema: &SchemaConfig) -> (Vec<SchemaError>, Defaults) {
+        let defaults = Defaults::load(path.parent().unwrap(), &BTreeMap::new()).unwrap();
+        let (header, _) =
+            parse_frontmatter_with_defaults::<Header>(source, &defaults.for_note(path)).unwrap();
+
+        (schema.check(&header.extra), defaults)
+    }
+
+    #[test]
+    fn notes_in_subdirectories() {
+        let dir = TempDir::new().unwrap();
+        let paths = ["a.dj", "physics/b.dj", "physics/quantum/c.dj"];
+
+        for path in paths {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        fs::write(dir.path().join("physics").join(DEFAULTS_FILE), "").unwrap();
+
+        let expected: Vec<_> = paths.iter().map(|path| dir.path().join(path)).collect();
+        assert_eq!(note_files(dir.path()).unwrap(), expected);
+    }
+
+    #[test]
+    fn field_ranges() {
+        let content = "title: A\nauthor: B\n";
+
+        assert_eq!(field_range(content, "author"), Some(9..15));
+        assert_eq!(field_range("author = \"B\"\n", "author"), Some(0..6));
+        assert_eq!(field_range(content, "auth"), None);
+    }
+
+    #[test]
+    fn defaults_glob_ranges() {
+        let config =
+            "[filters]\norder = [\"notes/*\"]\n\n[defaults]\n\"notes/*\" = { author = 1 }\n";
+        let range = defaults_glob_range(config, "notes/*");
+        assert_eq!(range.start, config.rfind("\"notes/*\"").unwrap());
+        assert_eq!(&config[range], "\"notes/*\"");
+
+        let config = "[defaults.'notes/*']\nauthor = 1\n";
+        assert_eq!(&config[defaults_glob_range(config, "notes/*")], "'notes/*'");
+
+        // Keys that are not found fall back to the header.
+        let config = "[defaults]\n\"notes/**\" = {}\n";
+        assert_eq!(&config[defaults_glob_range(config, "*")], "[defaults]");
+        assert_eq!(defaults_glob_range("", "*"), 0..0);
+    }
+
+    #[test]
+    fn field_errors_point_at_the_frontmatter() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("note.dj");
+        let source = "---\ntitle: A\nauthor: 1\n---\nbody\n";
+        let schema = schema("fields.author.type = \"string\"");
+
+        let (errors, defaults) = check(&path, source, &schema);
+        let location = locate_schema_error(&path, source, &defaults, "", &errors[0]);
+
+        assert_eq!(location.path, path);
+        assert_eq!(&source[location.range], "author");
+    }
+
+    #[test]
+    fn defaulted_field_errors_point_at_the_defaults() {
+        let dir = TempDir::new().unwrap();
+        let defaults_path = dir.path().join(DEFAULTS_FILE);
+        let defaults_source = "title: Untitled\nauthor: 1\n";
+        fs::write(&defaults_path, defaults_source).unwrap();
+
+        let path = dir.path().join("note.dj");
+        let source = "---\ntitle: A\n---\nbody\n";
+        let schema = schema("fields.author.type = \"string\"");
+
+        let (errors, defaults) = check(&path, source, &schema);
+        let location = locate_schema_error(&path, source, &defaults, "", &errors[0]);
+
+        assert_eq!(location.path, defaults_path);
+        assert_eq!(location.source, defaults_source);
+        assert_eq!(&defaults_source[location.range], "author");
+    }
+
+    #[test]
+    fn globbed_field_errors_point_at_the_config() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("note.dj");
+        let source = "---\ntitle: A\n---\nbody\n";
+        let config_source = "[defaults]\n\"*\" = { author = 1 }\n";
+        let config: Config = toml::from_str(config_source).unwrap();
+        let schema = schema("fields.author.type = \"string\"");
+
+        let defaults = Defaults::load(dir.path(), &config.defaults).unwrap();
+        let (header, _) =
+            parse_frontmatter_with_defaults::<Header>(source, &defaults.for_note(&path)).unwrap();
+        let errors = schema.check(&header.extra);
+        let location = locate_schema_error(&path, source, &defaults, config_source, &errors[0]);
+
+        assert_eq!(location.path, Path::new(CONFIG_FILE));
+        assert_eq!(&config_source[location.range], "\"*\"");
+    }
+
+    #[test]
+    fn missing_fields_point_at_the_frontmatter() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("note.dj");
+        let source = "---\ntitle: A\n---\nbody\n";
+        let schema = schema("fields.author.required = true");
+
+        let (errors, defaults) = check(&path, source, &schema);
+        let location = locate_schema_error(&path, source, &defaults, "", &errors[0]);
+
+        assert!(matches!(&errors[0], SchemaError::MissingField(field) if field == "author"));
+        assert_eq!(location.path, path);
+        assert_eq!(&source[location.range], "title: A\n");
+    }
+
+    #[test]
+    fn schema_errors_fail_at_error_level() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("note.dj");
+        let source = "---\ntitle: A\nauthor: 1\n---\nbody\n";
+        let defaults = Defaults::default();
+        let (header, _) = parse_frontmatter_with_defaults::<Header>(source, &[]).unwrap();
+
+        let warn = schema("fields.author.type = \"string\"");
+        assert!(check_schema(&path, source, &header, &defaults, "", &warn).is_ok());
+
+        let error = schema("level = \"error\"\nfields.author.type = \"string\"");
+        assert!(check_schema(&path, source, &header, &defaults, "", &error).is_err());
+    }
+}
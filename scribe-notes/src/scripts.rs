@@ -0,0 +1,829 @@
+//! User scripts, which transform the elements of notes and define helpers for
+//! the templates.
+//!
+//! Scripts are the `*.rhai` files in the scripts directory, written in
+//! [Rhai](https://rhai.rs). When they are loaded, they register functions:
+//!
+//! ```rhai
+//! // Links to Wikipedia, e.g. `[Djot](wiki:Djot)`.
+//! transform("link", |link| {
+//!     if link.destination.starts_with("wiki:") {
+//!         link.destination.replace("wiki:", "https://en.wikipedia.org/wiki/");
+//!         link
+//!     }
+//! });
+//!
+//! // `{{ shout(text = "hello") }}` in templates.
+//! helper("shout", |args| args.text.to_upper());
+//! ```
+
+use std::{
+    collections::HashMap,
+    ops::Range,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use jotdown::{AttributeKind, AttributeValue, Attributes, Container, Event};
+use rhai::{AST, Dynamic, Engine, EvalAltResult, FnPtr, Map};
+use scribe_common::djot::{Located, Strings};
+use thiserror::Error;
+use tracing::debug;
+
+use crate::config::ScriptsConfig;
+
+/// The functions registered by the scripts of a site.
+#[derive(Debug, Clone, Default)]
+pub struct Scripts {
+    engine: Arc<Engine>,
+    ast: Arc<AST>,
+    transforms: Vec<(Selector, FnPtr)>,
+    helpers: Vec<(String, FnPtr)>,
+}
+
+/// Functions registered while the scripts are run.
+#[derive(Debug, Default)]
+struct Registered {
+    transforms: Vec<(Selector, FnPtr)>,
+    helpers: Vec<(String, FnPtr)>,
+}
+
+impl Scripts {
+    /// Load the scripts in `dir`, in the order of their names.
+    ///
+    /// A missing directory has no scripts. Scripts that exceed the limits of
+    /// `config` fail, both while loading and when their functions are called.
+    pub fn load(dir: &Path, config: &ScriptsConfig) -> Result<Self> {
+        if !dir.exists() {
+            return Ok(Self::default());
+        }
+
+        let registered = Arc::new(Mutex::new(Registered::default()));
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(config.max_operations)
+            .set_max_call_levels(config.max_call_levels)
+            .set_max_expr_depths(config.max_expr_depth, config.max_function_expr_depth);
+
+        let transforms = registered.clone();
+        engine.register_fn(
+            "transform",
+            move |selector: &str, function: FnPtr| -> Result<(), Box<EvalAltResult>> {
+                let selector = selector.parse::<Selector>()?;
+                let mut registered = transforms.lock().unwrap();
+                registered.transforms.push((selector, function));
+                Ok(())
+            },
+        );
+
+        let helpers = registered.clone();
+        engine.register_fn("helper", move |name: &str, function: FnPtr| {
+            let mut registered = helpers.lock().unwrap();
+            registered.helpers.push((name.to_string(), function));
+        });
+
+        let pattern = dir.join("*.rhai");
+        let mut ast = AST::empty();
+
+        for entry in glob::glob(&pattern.to_string_lossy())? {
+            let path = entry?;
+            debug!("loading script: {}", path.display());
+
+            let script = engine
+                .compile_file(path.clone())
+                .with_context(|| format!("error compiling script {}", path.display()))?;
+            engine
+                .run_ast(&script)
+                .with_context(|| format!("error running script {}", path.display()))?;
+
+            ast.combine(script);
+        }
+
+        let registered = std::mem::take(&mut *registered.lock().unwrap());
+
+        Ok(Self {
+            engine: Arc::new(engine),
+            ast: Arc::new(ast),
+            transforms: registered.transforms,
+            helpers: registered.helpers,
+        })
+    }
+
+    /// The template helpers, by name.
+    pub fn helpers(&self) -> impl Iterator<Item = (&str, ScriptHelper)> {
+        self.helpers.iter().map(|(name, function)| {
+            let helper = ScriptHelper {
+                engine: self.engine.clone(),
+                ast: self.ast.clone(),
+                function: function.clone(),
+            };
+
+            (name.as_str(), helper)
+        })
+    }
+
+    /// The first transform registered for an element.
+    fn transform(&self, container: &Container, attributes: &Attributes) -> Option<&FnPtr> {
+        self.transforms
+            .iter()
+            .find(|(selector, _)| selector.matches(container, attributes))
+            .map(|(_, function)| function)
+    }
+
+    fn call(&self, function: &FnPtr, argument: Dynamic) -> Result<Dynamic, Box<EvalAltResult>> {
+        function.call(&self.engine, &self.ast, (argument,))
+    }
+}
+
+/// The elements a transform is registered for, e.g. `link` or `div.note`.
+#[derive(Debug, Clone)]
+struct Selector {
+    kind: String,
+    class: Option<String>,
+}
+
+impl Selector {
+    fn matches(&self, container: &Container, attributes: &Attributes) -> bool {
+        if kind(container) != Some(self.kind.as_str()) {
+            return false;
+        }
+
+        match &self.class {
+            Some(class) => classes(container, attributes).contains(class),
+            None => true,
+        }
+    }
+}
+
+impl FromStr for Selector {
+    type Err = Box<EvalAltResult>;
+
+    fn from_str(selector: &str) -> Result<Self, Self::Err> {
+        let (kind, class) = match selector.split_once('.') {
+            Some((kind, class)) => (kind, Some(class.to_string())),
+            None => (selector, None),
+        };
+
+        if !KINDS.contains(&kind) {
+            return Err(format!("unknown element `{}` in selector `{}`", kind, selector).into());
+        }
+
+        Ok(Self {
+            kind: kind.to_string(),
+            class,
+        })
+    }
+}
+
+/// The kinds of elements that can be transformed.
+const KINDS: &[&str] = &[
+    "div",
+    "span",
+    "link",
+    "image",
+    "heading",
+    "paragraph",
+    "code_block",
+    "blockquote",
+    "emphasis",
+    "strong",
+    "verbatim",
+];
+
+fn kind(container: &Container) -> Option<&'static str> {
+    let kind = match container {
+        Container::Div { .. } => "div",
+        Container::Span => "span",
+        Container::Link(..) => "link",
+        Container::Image(..) => "image",
+        Container::Heading { .. } => "heading",
+        Container::Paragraph => "paragraph",
+        Container::CodeBlock { .. } => "code_block",
+        Container::Blockquote => "blockquote",
+        Container::Emphasis => "emphasis",
+        Container::Strong => "strong",
+        Container::Verbatim => "verbatim",
+        _ => return None,
+    };
+
+    Some(kind)
+}
+
+/// The classes of an element, including the class of a div.
+fn classes(container: &Container, attributes: &Attributes) -> Vec<String> {
+    let mut classes = Vec::new();
+
+    if let Container::Div { class } = container {
+        classes.extend(class.split_whitespace().map(String::from));
+    }
+
+    for (kind, value) in attributes {
+        if *kind == AttributeKind::Class {
+            classes.extend(value.to_string().split_whitespace().map(String::from));
+        }
+    }
+
+    classes
+}
+
+/// Transform elements with the functions registered by scripts.
+///
+/// A transform is called with a map of the element:
+///
+/// - `kind`, e.g. `div` or `link`,
+/// - `attributes`, a map with the classes joined by spaces,
+/// - `text`, the text of the element,
+/// - `destination` of links and images, `language` of code blocks and
+///   `level` of headings.
+///
+/// It returns nothing to keep the element, HTML to replace it, or the map
+/// with changed `attributes`, `text`, `destination` or `language`. If the
+/// text is changed, it replaces the content of the element, in a paragraph if
+/// the element contains blocks. Elements inside a transformed element are not
+/// transformed. Strings that the changed elements borrow, like classes, are
+/// stored in `strings`.
+#[derive(Debug)]
+pub struct ScriptTransforms<'a, I> {
+    inner: I,
+    scripts: Scripts,
+    strings: &'a Strings,
+    buffer: Vec<Event<'a>>,
+    /// The range of the element that the buffered events replace.
+    range: Range<usize>,
+}
+
+impl<'a, I> ScriptTransforms<'a, I> {
+    pub fn new(inner: I, scripts: Scripts, strings: &'a Strings) -> Self {
+        Self {
+            inner,
+            scripts,
+            strings,
+            buffer: Vec::new(),
+            range: 0..0,
+        }
+    }
+}
+
+impl<'a, I> Iterator for ScriptTransforms<'a, I>
+where
+    I: Iterator<Item = (Event<'a>, Range<usize>)>,
+{
+    type Item = Result<(Event<'a>, Range<usize>), Located<ScriptError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.buffer.pop() {
+            return Some(Ok((event, self.range.clone())));
+        }
+
+        let (container, attributes, function) = match self.inner.next()? {
+            (Event::Start(container, attributes), range) => {
+                match self.scripts.transform(&container, &attributes) {
+                    Some(function) => {
+                        self.range = range;
+                        (container, attributes, function.clone())
+                    }
+                    None => return Some(Ok((Event::Start(container, attributes), range))),
+                }
+            }
+            event => return Some(Ok(event)),
+        };
+
+        let mut children = Vec::new();
+        let mut depth = 0;
+
+        for (event, range) in self.inner.by_ref() {
+            self.range.end = range.end;
+
+            match event {
+                Event::Start(..) => depth += 1,
+                Event::End(_) if depth == 0 => break,
+                Event::End(_) => depth -= 1,
+                _ => {}
+            }
+
+            children.push(event);
+        }
+
+        let element = Element::new(container, attributes, children);
+        let result = self
+            .scripts
+            .call(&function, element.to_dynamic())
+            .map_err(ScriptError::from)
+            .and_then(|result| element.apply(result, self.strings));
+
+        match result {
+            Ok(events) => self.buffer.extend(events.into_iter().rev()),
+            Err(err) => return Some(Err(Located::new(err, self.range.clone()))),
+        }
+
+        self.next()
+    }
+}
+
+/// An element passed to a transform.
+struct Element<'a> {
+    container: Container<'a>,
+    attributes: Attributes<'a>,
+    children: Vec<Event<'a>>,
+    text: String,
+}
+
+impl<'a> Element<'a> {
+    fn new(container: Container<'a>, attributes: Attributes<'a>, children: Vec<Event<'a>>) -> Self {
+        let mut text = String::new();
+
+        for event in &children {
+            match event {
+                Event::Str(str) => text.push_str(str),
+                Event::Softbreak | Event::Hardbreak => text.push(' '),
+                // Separate the text of blocks.
+                Event::End(container) if container.is_block() => text.push('\n'),
+                _ => {}
+            }
+        }
+
+        text.truncate(text.trim_end().len());
+
+        Self {
+            container,
+            attributes,
+            children,
+            text,
+        }
+    }
+
+    fn to_dynamic(&self) -> Dynamic {
+        let mut attributes = Map::new();
+
+        for (key, value) in self.attributes.unique_pairs() {
+            attributes.insert(key.into(), value.to_string().into());
+        }
+
+        let classes = classes(&self.container, &self.attributes);
+
+        if !classes.is_empty() {
+            attributes.insert("class".into(), classes.join(" ").into());
+        }
+
+        let mut map = Map::new();
+        map.insert(
+            "kind".into(),
+            kind(&self.container).unwrap_or_default().into(),
+        );
+        map.insert("attributes".into(), attributes.into());
+        map.insert("text".into(), self.text.clone().into());
+
+        match &self.container {
+            Container::Link(destination, _) | Container::Image(destination, _) => {
+                map.insert("destination".into(), destination.to_string().into());
+            }
+            Container::CodeBlock { language } => {
+                map.insert("language".into(), language.to_string().into());
+            }
+            Container::Heading { level, .. } => {
+                map.insert("level".into(), (*level as i64).into());
+            }
+            _ => {}
+        }
+
+        map.into()
+    }
+
+    /// The events that replace the element, as returned by a transform.
+    fn apply(self, result: Dynamic, strings: &'a Strings) -> Result<Vec<Event<'a>>, ScriptError> {
+        if result.is_unit() {
+            return Ok(self.events());
+        }
+
+        if result.is_string() {
+            let html = result.into_string().unwrap_or_default();
+            let container = match self.container.is_block() {
+                true => Container::RawBlock { format: "html" },
+                false => Container::RawInline { format: "html" },
+            };
+
+            return Ok(vec![
+                Event::Start(container.clone(), Attributes::new()),
+                Event::Str(html.into()),
+                Event::End(container),
+            ]);
+        }
+
+        let Some(mut map) = result.clone().try_cast::<Map>() else {
+            return Err(ScriptError::Return(result.type_name().to_string()));
+        };
+
+        let mut element = self;
+
+        if let Some(attributes) = map.remove("attributes") {
+            let attributes = attributes
+                .try_cast::<Map>()
+                .ok_or(ScriptError::Field("attributes", "a map"))?;
+            element.set_attributes(attributes, strings);
+        }
+
+        if let Some(destination) = map.remove("destination") {
+            let destination = string(destination, "destination")?;
+
+            match &mut element.container {
+                Container::Link(old, _) | Container::Image(old, _) => *old = destination.into(),
+                _ => {}
+            }
+        }
+
+        if let Some(language) = map.remove("language") {
+            let language = string(language, "language")?;
+
+            if let Container::CodeBlock { language: old } = &mut element.container {
+                *old = strings.alloc(&language);
+            }
+        }
+
+        if let Some(text) = map.remove("text") {
+            let text = string(text, "text")?;
+
+            if text != element.text {
+                element.children = match element.container.is_block_container() {
+                    true => vec![
+                        Event::Start(Container::Paragraph, Attributes::new()),
+                        Event::Str(text.into()),
+                        Event::End(Container::Paragraph),
+                    ],
+                    false => vec![Event::Str(text.into())],
+                };
+            }
+        }
+
+        Ok(element.events())
+    }
+
+    /// Replace the attributes, keeping the first class of a div as its class.
+    fn set_attributes(&mut self, attributes: Map, strings: &'a Strings) {
+        let mut result = Attributes::new();
+
+        if let Container::Div { class } = &mut self.container {
+            *class = "";
+        }
+
+        for (key, value) in attributes {
+            let value = value.to_string();
+
+            match key.as_str() {
+                "class" => {
+                    let mut classes = value.split_whitespace();
+
+                    if let Container::Div { class } = &mut self.container {
+                        *class = strings.alloc(classes.next().unwrap_or_default());
+                    }
+
+                    for class in classes {
+                        result.push((
+                            AttributeKind::Class,
+                            AttributeValue::from(class.to_string()),
+                        ));
+                    }
+                }
+                "id" => result.push((AttributeKind::Id, AttributeValue::from(value))),
+                key => {
+                    let kind = AttributeKind::Pair {
+                        key: strings.alloc(key),
+                    };
+                    result.push((kind, AttributeValue::from(value)));
+                }
+            }
+        }
+
+        self.attributes = result;
+    }
+
+    fn events(self) -> Vec<Event<'a>> {
+        let mut events = Vec::with_capacity(self.children.len() + 2);
+        events.push(Event::Start(self.container.clone(), self.attributes));
+        events.extend(self.children);
+        events.push(Event::End(self.container));
+        events
+    }
+}
+
+fn string(value: Dynamic, field: &'static str) -> Result<String, ScriptError> {
+    value
+        .into_string()
+        .map_err(|_| ScriptError::Field(field, "a string"))
+}
+
+/// A template helper defined by a script.
+///
+/// The helper is called with a map of the arguments of the template function.
+pub struct ScriptHelper {
+    engine: Arc<Engine>,
+    ast: Arc<AST>,
+    function: FnPtr,
+}
+
+impl tera::Function for ScriptHelper {
+    fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+        let args =
+            rhai::serde::to_dynamic(args).map_err(|err| tera::Error::msg(err.to_string()))?;
+        let result: Dynamic = self
+            .function
+            .call(&self.engine, &self.ast, (args,))
+            .map_err(|err| tera::Error::msg(err.to_string()))?;
+
+        rhai::serde::from_dynamic(&result).map_err(|err| tera::Error::msg(err.to_string()))
+    }
+}
+
+/// Error produced by [`ScriptTransforms`].
+#[derive(Debug, Error)]
+pub enum ScriptError {
+    /// Error while running a transform.
+    #[error("script failed: {0}")]
+    Eval(#[from] Box<EvalAltResult>),
+    /// A transform returned a value that is not an element.
+    #[error("script returned {0}, expected nothing, a string or a map")]
+    Return(String),
+    /// A field of the returned element has the wrong type.
+    #[error("`{0}` returned by script is not {1}")]
+    Field(&'static str, &'static str),
+}
+
+#[cfg(test)]
+mod tests {
+    use scribe_common::djot::{Diagnostics, ErrorStyle, ShowErrors};
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn load(script: &str, config: &ScriptsConfig) -> Result<Scripts> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.path().join("test.rhai"), script)?;
+        Scripts::load(dir.path(), config)
+    }
+
+    fn render(scripts: Scripts, source: &str, diagnostics: &Diagnostics) -> String {
+        let strings = Strings::new();
+        let events = jotdown::Parser::new(source).into_offset_iter();
+        let events = ScriptTransforms::new(events, scripts, &strings);
+        let events = ShowErrors::new(events)
+            .with_style(ErrorStyle::Message)
+            .with_diagnostics(diagnostics.clone());
+        jotdown::html::render_to_string(events.map(|(event, _)| event))
+    }
+
+    /// The error of the first element that a transform is called on.
+    fn transform_error(script: &str, config: &ScriptsConfig) -> ScriptError {
+        let scripts = load(script, config).unwrap();
+        let strings = Strings::new();
+        let events = jotdown::Parser::new("*a*\n").into_offset_iter();
+
+        ScriptTransforms::new(events, scripts, &strings)
+            .find_map(Result::err)
+            .unwrap()
+            .error
+    }
+
+    fn attributes(pairs: &[(AttributeKind<'static>, &'static str)]) -> Attributes<'static> {
+        let mut attributes = Attributes::new();
+
+        for (kind, value) in pairs {
+            attributes.push((*kind, AttributeValue::from(*value)));
+        }
+
+        attributes
+    }
+
+    fn element(container: Container) -> Element {
+        let children = vec![Event::Str("text".into())];
+        Element::new(container, Attributes::new(), children)
+    }
+
+    #[test]
+    fn parse_selectors() {
+        let selector: Selector = "link".parse().unwrap();
+        assert_eq!((selector.kind.as_str(), selector.class), ("link", None));
+
+        let selector: Selector = "div.note".parse().unwrap();
+        assert_eq!(selector.kind, "div");
+        assert_eq!(selector.class.as_deref(), Some("note"));
+
+        let error = "table.wide".parse::<Selector>().unwrap_err();
+        assert!(error.to_string().contains("unknown element `table`"));
+    }
+
+    #[test]
+    fn selectors_match_classes() {
+        let selector: Selector = "div.note".parse().unwrap();
+        let classes = attributes(&[(AttributeKind::Class, "note")]);
+
+        assert!(selector.matches(&Container::Div { class: "note" }, &Attributes::new()));
+        assert!(selector.matches(&Container::Div { class: "" }, &classes));
+        assert!(!selector.matches(&Container::Div { class: "" }, &Attributes::new()));
+        assert!(!selector.matches(&Container::Span, &classes));
+    }
+
+    #[test]
+    fn apply_unit_keeps_element() {
+        let strings = Strings::new();
+        let events = element(Container::Emphasis).apply(Dynamic::UNIT, &strings);
+
+        assert_eq!(
+            events.unwrap(),
+            vec![
+                Event::Start(Container::Emphasis, Attributes::new()),
+                Event::Str("text".into()),
+                Event::End(Container::Emphasis),
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_string_replaces_element_with_html() {
+        let strings = Strings::new();
+        let html = Dynamic::from("<b>x</b>".to_string());
+
+        let events = element(Container::Emphasis).apply(html.clone(), &strings);
+        assert_eq!(
+            events.unwrap()[0],
+            Event::Start(Container::RawInline { format: "html" }, Attributes::new())
+        );
+
+        let events = element(Container::Paragraph).apply(html, &strings).unwrap();
+        assert_eq!(
+            events,
+            vec![
+                Event::Start(Container::RawBlock { format: "html" }, Attributes::new()),
+                Event::Str("<b>x</b>".into()),
+                Event::End(Container::RawBlock { format: "html" }),
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_map_changes_element() {
+        let strings = Strings::new();
+        let mut map = Map::new();
+        map.insert("language".into(), "python".into());
+        map.insert("text".into(), "print()".into());
+
+        let events = element(Container::CodeBlock { language: "rust" })
+            .apply(map.into(), &strings)
+            .unwrap();
+        let code_block = Container::CodeBlock { language: "python" };
+
+        assert_eq!(
+            events,
+            vec![
+                Event::Start(code_block.clone(), Attributes::new()),
+                Event::Str("print()".into()),
+                Event::End(code_block),
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_other_values_fails() {
+        let strings = Strings::new();
+
+        let result = element(Container::Emphasis).apply(Dynamic::from(1_i64), &strings);
+        assert!(matches!(result, Err(ScriptError::Return(_))));
+
+        let mut map = Map::new();
+        map.insert("text".into(), Dynamic::from(1_i64));
+        let result = element(Container::Emphasis).apply(map.into(), &strings);
+        assert!(matches!(result, Err(ScriptError::Field("text", _))));
+    }
+
+    #[test]
+    fn set_attributes_of_div() {
+        let strings = Strings::new();
+        let mut element = element(Container::Div { class: "old" });
+        let mut map = Map::new();
+        map.insert("class".into(), "note wide".into());
+        map.insert("id".into(), "first".into());
+        map.insert("key".into(), "value".into());
+
+        element.set_attributes(map, &strings);
+
+        assert_eq!(element.container, Container::Div { class: "note" });
+        assert_eq!(
+            element.attributes,
+            attributes(&[
+                (AttributeKind::Class, "wide"),
+                (AttributeKind::Id, "first"),
+                (AttributeKind::Pair { key: "key" }, "value"),
+            ])
+        );
+    }
+
+    #[test]
+    fn set_attributes_of_other_containers() {
+        let strings = Strings::new();
+        let mut element = element(Container::Span);
+        let mut map = Map::new();
+        map.insert("class".into(), "note wide".into());
+
+        element.set_attributes(map, &strings);
+
+        // Only a div keeps its first class in the container.
+        assert_eq!(element.container, Container::Span);
+        assert_eq!(
+            element.attributes,
+            attributes(&[
+                (AttributeKind::Class, "note"),
+                (AttributeKind::Class, "wide")
+            ])
+        );
+    }
+
+    #[test]
+    fn transforms_change_elements() {
+        let script = r#"
+            transform("link", |link| {
+                link.destination = "https://example.com/" + link.destination;
+                link
+            });
+            transform("div.note", |div| `<aside>${div.text}</aside>`);
+        "#;
+        let scripts = load(script, &ScriptsConfig::default()).unwrap();
+        let diagnostics = Diagnostics::new();
+
+        let html = render(scripts, "[a](b)\n\n::: note\nc\n:::\n", &diagnostics);
+
+        assert_eq!(
+            html,
+            "<p><a href=\"https://example.com/b\">a</a></p>\n<aside>c</aside>\n"
+        );
+        assert!(diagnostics.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn transform_errors_are_shown() {
+        let script = r#"transform("emphasis", |element| throw "bad element");"#;
+        let scripts = load(script, &ScriptsConfig::default()).unwrap();
+        let diagnostics = Diagnostics::new();
+
+        let html = render(scripts, "a _b_ c\n", &diagnostics);
+
+        assert!(html.starts_with("<p>a <span class=\"error\">script failed: "));
+        assert!(html.contains("bad element"));
+        assert!(html.ends_with("</span> c</p>\n"));
+        let diagnostics = diagnostics.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range, 2..5);
+    }
+
+    #[test]
+    fn operations_are_limited() {
+        let config = ScriptsConfig {
+            max_operations: 1000,
+            ..Default::default()
+        };
+        let script = r#"transform("strong", |element| { loop {} });"#;
+
+        let error = transform_error(script, &config);
+        assert!(matches!(
+            error,
+            ScriptError::Eval(error) if matches!(*error, EvalAltResult::ErrorTooManyOperations(_))
+        ));
+    }
+
+    #[test]
+    fn call_levels_are_limited() {
+        let config = ScriptsConfig {
+            max_call_levels: 8,
+            ..Default::default()
+        };
+        let script = r#"
+            fn deep(n) { deep(n + 1) }
+            transform("strong", |element| deep(0));
+        "#;
+
+        let error = transform_error(script, &config);
+        assert!(matches!(
+            error,
+            ScriptError::Eval(error) if matches!(*error, EvalAltResult::ErrorStackOverflow(_))
+        ));
+    }
+
+    #[test]
+    fn expression_depth_is_limited() {
+        let config = ScriptsConfig {
+            max_expr_depth: 8,
+            ..Default::default()
+        };
+        let script = format!("let x = {}1{};", "(".repeat(20), ")".repeat(20));
+
+        let error = load(&script, &config).unwrap_err();
+        assert!(error.to_string().starts_with("error compiling script"));
+    }
+
+    #[test]
+    fn missing_directory_has_no_scripts() {
+        let dir = TempDir::new().unwrap();
+        let scripts = Scripts::load(&dir.path().join("scripts"), &ScriptsConfig::default());
+
+        assert!(scripts.unwrap().transforms.is_empty());
+    }
+}
@@ -2,7 +2,7 @@ use anyhow::Result;
 use serde::Serialize;
 use tera::Tera;
 
-use crate::header::Header;
+use crate::{header::Header, scripts::Scripts};
 
 pub struct Templates {
     tera: Tera,
@@ -14,6 +14,13 @@ impl Templates {
         Ok(Self { tera })
     }
 
+    /// Register the template helpers defined by user scripts as functions.
+    pub fn register_helpers(&mut self, scripts: &Scripts) {
+        for (name, helper) in scripts.helpers() {
+            self.tera.register_function(name, helper);
+        }
+    }
+
     pub fn render_index(&self, notes: &[NoteData]) -> Result<String> {
         let mut ctx = tera::Context::new();
         ctx.insert("notes", &notes);
@@ -26,8 +33,10 @@ impl Templates {
         ctx.insert("meta", &header);
         ctx.insert("title", &header.title);
         ctx.insert("date", &header.date);
+        ctx.insert("extra", &header.extra);
         ctx.insert("body", &body);
-        let html = self.tera.render("note.html", &ctx)?;
+        let template = header.template.as_deref().unwrap_or("note.html");
+        let html = self.tera.render(template, &ctx)?;
         Ok(html)
     }
 }